@@ -0,0 +1,67 @@
+//! Compares recording values with `DDSketch::accept_with_count`, which checks the count and the
+//! accept bounds of every value, and with `DDSketch::accept_unchecked`, which does not. Run with
+//! `cargo bench --bench accept`.
+
+use sketches_rust::DDSketch;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const NUM_VALUES: usize = 1 << 20;
+const ROUNDS: u32 = 20;
+
+fn time_per_value(mut f: impl FnMut()) -> f64 {
+    f();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    let elapsed: Duration = start.elapsed() / ROUNDS;
+    elapsed.as_nanos() as f64 / NUM_VALUES as f64
+}
+
+fn main() {
+    // Pseudo-random values of both signs spanning six orders of magnitude, from a xorshift
+    // generator.
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let values: Vec<f64> = (0..NUM_VALUES)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let magnitude = 10f64.powf((state >> 11) as f64 / (1u64 << 53) as f64 * 6.0 - 3.0);
+            if state & 1 == 0 {
+                magnitude
+            } else {
+                -magnitude
+            }
+        })
+        .collect();
+
+    // Values within the zero threshold skip the indexing and the stores, which leaves the checks
+    // and the classification of the values to compare.
+    let zeros: Vec<f64> = values.iter().map(|value| value * 1e-4).collect();
+    for (name, values, zero_threshold) in [("indexed", &values, 0.0), ("zero band", &zeros, 1.0)] {
+        let mut sketch = DDSketch::unbounded_dense(0.01)
+            .unwrap()
+            .with_zero_threshold(zero_threshold);
+        sketch.set_accept_bounds(-1e4, 1e4).unwrap();
+        let checked = time_per_value(|| {
+            sketch.clear();
+            for value in values {
+                sketch.accept_with_count(*value, 1.0);
+            }
+            black_box(&sketch);
+        });
+        let unchecked = time_per_value(|| {
+            sketch.clear();
+            for value in values {
+                sketch.accept_unchecked(*value, 1.0);
+            }
+            black_box(&sketch);
+        });
+        println!(
+            "{:<10} accept_with_count: {:.2} ns/value, accept_unchecked: {:.2} ns/value",
+            name, checked, unchecked
+        );
+    }
+}
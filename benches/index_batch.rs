@@ -0,0 +1,71 @@
+//! Compares indexing and accepting values one by one with the batched `IndexMapping::index_batch`
+//! and `DDSketch::accept_many`. Run with `cargo bench --bench index_batch`.
+
+use sketches_rust::{DDSketch, IndexMapping, IndexMappingLayout};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const NUM_VALUES: usize = 1 << 20;
+const ROUNDS: u32 = 20;
+
+fn time_per_value(mut f: impl FnMut()) -> f64 {
+    f();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    let elapsed: Duration = start.elapsed() / ROUNDS;
+    elapsed.as_nanos() as f64 / NUM_VALUES as f64
+}
+
+fn main() {
+    // Pseudo-random values spanning nine orders of magnitude, from a xorshift generator.
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let values: Vec<f64> = (0..NUM_VALUES)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            10f64.powf((state >> 11) as f64 / (1u64 << 53) as f64 * 9.0 - 3.0)
+        })
+        .collect();
+
+    for layout in [IndexMappingLayout::LOG, IndexMappingLayout::LogCubic] {
+        let mapping = IndexMapping::with_relative_accuracy(layout, 0.01).unwrap();
+        let mut indexes = vec![0; NUM_VALUES];
+        let scalar = time_per_value(|| {
+            for (index, value) in indexes.iter_mut().zip(&values) {
+                *index = mapping.index(*value);
+            }
+            black_box(&indexes);
+        });
+        let batch = time_per_value(|| {
+            mapping.index_batch(&values, &mut indexes);
+            black_box(&indexes);
+        });
+        println!(
+            "{:<10} index: {:.2} ns/value, index_batch: {:.2} ns/value",
+            layout.as_str(),
+            scalar,
+            batch
+        );
+    }
+
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    let scalar = time_per_value(|| {
+        sketch.clear();
+        for value in &values {
+            sketch.accept(*value);
+        }
+        black_box(&sketch);
+    });
+    let batch = time_per_value(|| {
+        sketch.clear();
+        sketch.accept_many(&values);
+        black_box(&sketch);
+    });
+    println!(
+        "{:<10} accept: {:.2} ns/value, accept_many: {:.2} ns/value",
+        "LogCubic", scalar, batch
+    );
+}
@@ -3,5 +3,6 @@ fn main() {
         .cargo_out_dir("protos")
         .include("src")
         .input("src/protos/ddsketch.proto")
+        .input("src/protos/agent_payload.proto")
         .run_from_script();
 }
@@ -1,8 +1,11 @@
 use std::{fmt, io};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Error {
     InvalidArgument(&'static str),
+    RelativeAccuracyOutOfRange,
+    MaxNumBinsTooSmall,
+    MaxNumBinsTooLarge,
     IoError(io::ErrorKind),
 }
 
@@ -10,11 +13,20 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::InvalidArgument(arg) => write!(f, "Invalid argument: {}", arg),
+            Error::RelativeAccuracyOutOfRange => {
+                write!(f, "The relative accuracy must be between 0 and 1.")
+            }
+            Error::MaxNumBinsTooSmall => write!(f, "The maximum number of bins must be positive."),
+            Error::MaxNumBinsTooLarge => {
+                write!(f, "The maximum number of bins must be at most 2^30.")
+            }
             Error::IoError(ref cause) => write!(f, "Io Error: {}", cause),
         }
     }
 }
 
+impl std::error::Error for Error {}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         Error::IoError(error.kind())
@@ -1,6 +1,7 @@
 use crate::sketch::{Flag, FlagType};
 use crate::{serde, Error};
 use libm::cbrt;
+use std::fmt;
 
 use crate::output::Output;
 
@@ -118,15 +119,7 @@ impl IndexMapping {
                 _index_offset,
                 _multiplier,
                 _relative_accuracy,
-            ) => {
-                let long_bits = value.to_bits() as i64;
-                let s: f64 = serde::get_significand_plus_one(long_bits) - 1.0;
-                let e: f64 = serde::get_exponent(long_bits) as f64;
-                ((CUBICALLY_INTERPOLATED_MAPPING_A * s + CUBICALLY_INTERPOLATED_MAPPING_B) * s
-                    + CUBICALLY_INTERPOLATED_MAPPING_C)
-                    * s
-                    + e
-            }
+            ) => cubic_log(value),
         }
     }
 
@@ -170,11 +163,30 @@ impl IndexMapping {
     }
 
     pub fn index(&self, value: f64) -> i32 {
-        let index: f64 = self.log(value) * self.multiplier() + self.index_offset();
-        if index >= 0.0 {
-            index as i32
-        } else {
-            (index - 1.0) as i32
+        floor_index(self.log(value) * self.multiplier() + self.index_offset())
+    }
+
+    /// Computes the index of each value of `values` into `out`, which must have the same length.
+    /// The mapping parameters are resolved once for the whole slice rather than for every value.
+    pub fn index_batch(&self, values: &[f64], out: &mut [i32]) {
+        assert_eq!(
+            values.len(),
+            out.len(),
+            "The values and the indexes must have the same length."
+        );
+        let multiplier = self.multiplier();
+        let index_offset = self.index_offset();
+        match self {
+            IndexMapping::LogarithmicMapping(..) => {
+                for (index, value) in out.iter_mut().zip(values) {
+                    *index = floor_index(value.ln() * multiplier + index_offset);
+                }
+            }
+            IndexMapping::CubicallyInterpolatedMapping(..) => {
+                for (index, value) in out.iter_mut().zip(values) {
+                    *index = floor_index(cubic_log(*value) * multiplier + index_offset);
+                }
+            }
         }
     }
 
@@ -202,7 +214,7 @@ impl IndexMapping {
     }
 
     pub(crate) fn max_indexable_value(&self) -> f64 {
-        f64::max(
+        f64::min(
             f64::powf(
                 2.0,
                 (i32::MAX as f64 - self.index_offset()) / self.multiplier() - 1.0,
@@ -211,6 +223,19 @@ impl IndexMapping {
         )
     }
 
+    // Shifts the index offset by a whole number of bins so that values close to `value` map
+    // near index 0, which keeps the bin boundaries unchanged.
+    pub(crate) fn with_center_value(&self, value: f64) -> Result<IndexMapping, Error> {
+        if !value.is_finite() || value == 0.0 {
+            return Err(Error::InvalidArgument(
+                "The center value must be finite and non-zero.",
+            ));
+        }
+        let index_offset = self.index_offset()
+            - (self.log(value.abs()) * self.multiplier() + self.index_offset()).round();
+        IndexMapping::with_gamma_offset(self.layout(), self.gamma(), index_offset)
+    }
+
     pub fn encode(&self, output: &mut Output) -> Result<(), Error> {
         self.layout().to_flag().encode(output)?;
         output.write_double_le(self.gamma())?;
@@ -222,20 +247,12 @@ impl IndexMapping {
         index_layout: IndexMappingLayout,
         relative_accuracy: f64,
     ) -> Result<IndexMapping, Error> {
-        if relative_accuracy <= 0.0 || relative_accuracy >= 1.0 {
-            return Err(Error::InvalidArgument(
-                "The relative accuracy must be between 0 and 1.",
-            ));
+        if relative_accuracy.is_nan() || relative_accuracy <= 0.0 || relative_accuracy >= 1.0 {
+            return Err(Error::RelativeAccuracyOutOfRange);
         }
 
         match index_layout {
             IndexMappingLayout::LOG => {
-                if relative_accuracy <= 0.0 || relative_accuracy >= 1.0 {
-                    return Err(Error::InvalidArgument(
-                        "The relative accuracy must be between 0 and 1.",
-                    ));
-                }
-
                 let gamma =
                     calculate_gamma(relative_accuracy, LOGARITHMIC_MAPPING_CORRECTING_FACTOR);
                 let index_offset: f64 = 0.0;
@@ -272,6 +289,15 @@ impl IndexMapping {
         }
     }
 
+    pub fn with_gamma(index_layout: IndexMappingLayout, gamma: f64) -> Result<IndexMapping, Error> {
+        if gamma.is_nan() || gamma <= 1.0 || gamma.is_infinite() {
+            return Err(Error::InvalidArgument(
+                "The gamma must be finite and greater than 1.",
+            ));
+        }
+        IndexMapping::with_gamma_offset(index_layout, gamma, 0.0)
+    }
+
     pub fn with_gamma_offset(
         index_layout: IndexMappingLayout,
         gamma: f64,
@@ -307,8 +333,48 @@ impl IndexMapping {
             _ => Err(Error::InvalidArgument("Unsupported IndexLayout")),
         }
     }
+
+    /// Builds a mapping like `with_gamma_offset`, but with `multiplier` instead of the one derived
+    /// from gamma, to index exactly like other libraries. The multiplier is not encoded, so a
+    /// decoded mapping derives it from gamma again.
+    pub fn with_gamma_offset_multiplier(
+        index_layout: IndexMappingLayout,
+        gamma: f64,
+        index_offset: f64,
+        multiplier: f64,
+    ) -> Result<IndexMapping, Error> {
+        if !(multiplier.is_finite() && multiplier > 0.0) {
+            return Err(Error::InvalidArgument(
+                "The multiplier must be finite and positive.",
+            ));
+        }
+        Ok(
+            match IndexMapping::with_gamma_offset(index_layout, gamma, index_offset)? {
+                IndexMapping::LogarithmicMapping(gamma, index_offset, _, relative_accuracy) => {
+                    IndexMapping::LogarithmicMapping(
+                        gamma,
+                        index_offset,
+                        multiplier,
+                        relative_accuracy,
+                    )
+                }
+                IndexMapping::CubicallyInterpolatedMapping(
+                    gamma,
+                    index_offset,
+                    _,
+                    relative_accuracy,
+                ) => IndexMapping::CubicallyInterpolatedMapping(
+                    gamma,
+                    index_offset,
+                    multiplier,
+                    relative_accuracy,
+                ),
+            },
+        )
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum IndexMappingLayout {
     LOG = 0,
     LogLinear = 1,
@@ -334,6 +400,65 @@ impl IndexMappingLayout {
         let sub_flag = self as u8;
         Flag::with_type(FlagType::IndexMapping, sub_flag)
     }
+
+    /// Returns the name of the layout, as used by `Display` and for labels.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IndexMappingLayout::LOG => "LOG",
+            IndexMappingLayout::LogLinear => "LogLinear",
+            IndexMappingLayout::LogQuadratic => "LogQuadratic",
+            IndexMappingLayout::LogCubic => "LogCubic",
+            IndexMappingLayout::LogQuartic => "LogQuartic",
+        }
+    }
+}
+
+impl fmt::Display for IndexMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(gamma={})", self.layout().as_str(), self.gamma())
+    }
+}
+
+/// Returns the gamma of a logarithmic mapping that guarantees `relative_accuracy`.
+pub fn gamma_from_relative_accuracy(relative_accuracy: f64) -> Result<f64, Error> {
+    if relative_accuracy.is_nan() || relative_accuracy <= 0.0 || relative_accuracy >= 1.0 {
+        return Err(Error::RelativeAccuracyOutOfRange);
+    }
+    Ok(calculate_gamma(
+        relative_accuracy,
+        LOGARITHMIC_MAPPING_CORRECTING_FACTOR,
+    ))
+}
+
+/// Returns the relative accuracy guaranteed by a logarithmic mapping with `gamma`.
+pub fn relative_accuracy_from_gamma(gamma: f64) -> Result<f64, Error> {
+    if gamma.is_nan() || gamma <= 1.0 || gamma.is_infinite() {
+        return Err(Error::InvalidArgument(
+            "The gamma must be finite and greater than 1.",
+        ));
+    }
+    Ok(calculate_relative_accuracy(
+        gamma,
+        LOGARITHMIC_MAPPING_CORRECTING_FACTOR,
+    ))
+}
+
+fn floor_index(index: f64) -> i32 {
+    if index >= 0.0 {
+        index as i32
+    } else {
+        (index - 1.0) as i32
+    }
+}
+
+fn cubic_log(value: f64) -> f64 {
+    let long_bits = value.to_bits() as i64;
+    let s: f64 = serde::get_significand_plus_one(long_bits) - 1.0;
+    let e: f64 = serde::get_exponent(long_bits) as f64;
+    ((CUBICALLY_INTERPOLATED_MAPPING_A * s + CUBICALLY_INTERPOLATED_MAPPING_B) * s
+        + CUBICALLY_INTERPOLATED_MAPPING_C)
+        * s
+        + e
 }
 
 fn calculate_relative_accuracy(gamma: f64, correcting_factor: f64) -> f64 {
@@ -348,8 +473,11 @@ fn calculate_gamma(relative_accuracy: f64, correcting_factor: f64) -> f64 {
 
 #[cfg(test)]
 mod tests {
-    use crate::index_mapping::IndexMapping;
     use crate::index_mapping::IndexMappingLayout::{LogCubic, LOG};
+    use crate::index_mapping::{
+        gamma_from_relative_accuracy, relative_accuracy_from_gamma, IndexMapping,
+        IndexMappingLayout,
+    };
 
     const TEST_GAMMAS: [f64; 3] = [1.0 + 1e-6, 1.02, 1.5];
     const TEST_INDEX_OFFSETS: [f64; 4] = [0.0, 1.0, -12.23, 7768.3];
@@ -558,4 +686,100 @@ mod tests {
             assert_eq!(indexes[i], mapping.index(values[i]));
         }
     }
+
+    #[test]
+    fn test_with_gamma() {
+        for layout in [LOG, LogCubic] {
+            let expected = IndexMapping::with_relative_accuracy(layout, 2e-2).unwrap();
+            let mapping = IndexMapping::with_gamma(layout, expected.gamma()).unwrap();
+            assert_eq!(expected.index_offset(), mapping.index_offset());
+            let mut value = 1e-6;
+            while value < 1e6 {
+                assert_eq!(expected.index(value), mapping.index(value));
+                value *= 1.1;
+            }
+        }
+        assert!(IndexMapping::with_gamma(LOG, 1.0).is_err());
+        assert!(IndexMapping::with_gamma(LOG, f64::NAN).is_err());
+        assert!(IndexMapping::with_gamma(LogCubic, f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn test_index_batch() {
+        let mut values = Vec::new();
+        let mut value = 1e-300;
+        while value < 1e300 {
+            values.push(value);
+            value *= 1.37;
+        }
+        let mut indexes = vec![0; values.len()];
+        for layout in [LOG, LogCubic] {
+            for gamma in TEST_GAMMAS {
+                for index_offset in TEST_INDEX_OFFSETS {
+                    let mapping =
+                        IndexMapping::with_gamma_offset(layout, gamma, index_offset).unwrap();
+                    mapping.index_batch(&values, &mut indexes);
+                    for (value, index) in values.iter().zip(&indexes) {
+                        assert_eq!(mapping.index(*value), *index);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_gamma_relative_accuracy_conversion() {
+        for relative_accuracy in [1e-6, 0.001, 0.01, 0.02, 0.5, 0.9] {
+            let gamma = gamma_from_relative_accuracy(relative_accuracy).unwrap();
+            let mapping = IndexMapping::with_relative_accuracy(LOG, relative_accuracy).unwrap();
+            assert!((gamma - mapping.gamma()).abs() <= EPSILON * gamma);
+            let restored = relative_accuracy_from_gamma(gamma).unwrap();
+            assert!((restored - relative_accuracy).abs() <= EPSILON * relative_accuracy);
+        }
+        for gamma in TEST_GAMMAS {
+            let relative_accuracy = relative_accuracy_from_gamma(gamma).unwrap();
+            let restored = gamma_from_relative_accuracy(relative_accuracy).unwrap();
+            assert!((restored - gamma).abs() <= EPSILON * gamma);
+        }
+        assert!(gamma_from_relative_accuracy(0.0).is_err());
+        assert!(gamma_from_relative_accuracy(1.0).is_err());
+        assert!(gamma_from_relative_accuracy(f64::NAN).is_err());
+        assert!(relative_accuracy_from_gamma(1.0).is_err());
+        assert!(relative_accuracy_from_gamma(f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let mapping = IndexMapping::with_gamma_offset(LogCubic, 1.02, 0.0).unwrap();
+        assert_eq!("LogCubic(gamma=1.02)", mapping.to_string());
+        let mapping = IndexMapping::with_relative_accuracy(LOG, 0.01).unwrap();
+        let displayed = mapping.to_string();
+        assert!(displayed.starts_with("LOG(gamma="));
+        assert!(displayed.contains(&mapping.gamma().to_string()));
+        assert_eq!("LogQuartic", IndexMappingLayout::LogQuartic.as_str());
+    }
+
+    #[test]
+    fn test_with_gamma_offset_multiplier() {
+        let mapping = IndexMapping::with_gamma_offset_multiplier(LOG, 1.02, 3.0, 100.0).unwrap();
+        assert_eq!(100.0, mapping.multiplier());
+        assert_eq!(1.02, mapping.gamma());
+        assert_eq!(3.0, mapping.index_offset());
+        for (value, index) in [
+            (1.0, 3),
+            (std::f64::consts::E, 103),
+            (0.5, -67),
+            (10.0, 233),
+        ] {
+            assert_eq!(index, mapping.index(value));
+        }
+
+        let derived = IndexMapping::with_gamma_offset(LogCubic, 1.02, 0.0).unwrap();
+        let overridden =
+            IndexMapping::with_gamma_offset_multiplier(LogCubic, 1.02, 0.0, derived.multiplier())
+                .unwrap();
+        assert_eq!(derived, overridden);
+        assert!(IndexMapping::with_gamma_offset_multiplier(LOG, 1.02, 0.0, 0.0).is_err());
+        assert!(IndexMapping::with_gamma_offset_multiplier(LOG, 1.02, 0.0, f64::NAN).is_err());
+    }
 }
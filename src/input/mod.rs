@@ -2,13 +2,13 @@ use crate::error::Error;
 use std::io::ErrorKind;
 
 pub struct Input<'a> {
-    vec: &'a Vec<u8>,
+    vec: &'a [u8],
     pos: usize,
     end: usize,
 }
 
 impl<'a> Input<'a> {
-    pub fn wrap(vec: &'a Vec<u8>) -> Input {
+    pub fn wrap(vec: &'a [u8]) -> Input {
         Input {
             pos: 0,
             end: vec.len(),
@@ -29,6 +29,14 @@ impl<'a> Input<'a> {
         Ok(value)
     }
 
+    pub(crate) fn skip(&mut self, length: usize) -> Result<(), Error> {
+        if length > self.end - self.pos {
+            return Err(Error::IoError(ErrorKind::UnexpectedEof));
+        }
+        self.pos += length;
+        Ok(())
+    }
+
     fn read_long_le(&mut self) -> Result<u64, Error> {
         if self.pos + 8 > self.end {
             return Err(Error::IoError(ErrorKind::UnexpectedEof));
@@ -0,0 +1,302 @@
+use crate::error::Error;
+use crate::index_mapping::{IndexMapping, IndexMappingLayout};
+use crate::store::{Store, UnboundedSizeDenseStore};
+use crate::{relative_accuracy_from_gamma, DDSketch};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+const INVALID_JSON: Error = Error::InvalidArgument("Invalid JSON.");
+// The output of `to_json` is nested 3 levels deep, anything much deeper is not a sketch.
+const MAX_DEPTH: usize = 64;
+
+impl DDSketch {
+    /// Dumps the index mapping, the zero count and the bins as human-readable JSON. This is meant
+    /// for inspection only, use `encode` or the protobuf messages to exchange sketches. JSON cannot
+    /// represent non-finite numbers, which are written as `null` and rejected by `from_json`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            "{{\"mapping\":{{\"layout\":\"{}\",\"gamma\":{},\"index_offset\":{}}},\"zero_count\":{}",
+            self.index_mapping.layout().as_str(),
+            JsonNumber(self.index_mapping.gamma()),
+            JsonNumber(self.index_mapping.index_offset()),
+            JsonNumber(self.zero_count)
+        );
+        for (name, store) in [
+            ("positive", &self.positive_value_store),
+            ("negative", &self.negative_value_store),
+        ] {
+            let _ = write!(json, ",\"{}\":[", name);
+            for (i, bin) in store.get_ascending_iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                let _ = write!(
+                    json,
+                    "{{\"index\":{},\"value\":{},\"count\":{}}}",
+                    bin.index,
+                    JsonNumber(self.index_mapping.value(bin.index)),
+                    JsonNumber(bin.count)
+                );
+            }
+            json.push(']');
+        }
+        json.push('}');
+        json
+    }
+
+    /// Parses the output of `to_json` into a sketch backed by unbounded dense stores. The bin
+    /// values are informative and ignored, the bins are restored from their indexes.
+    pub fn from_json(s: &str) -> Result<DDSketch, Error> {
+        let mut parser = Parser {
+            bytes: s.as_bytes(),
+            position: 0,
+            depth: 0,
+        };
+        let json = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() {
+            return Err(INVALID_JSON);
+        }
+
+        let mapping = json.get("mapping")?;
+        let layout = match mapping.get("layout")?.as_str()? {
+            "LOG" => IndexMappingLayout::LOG,
+            "LogLinear" => IndexMappingLayout::LogLinear,
+            "LogQuadratic" => IndexMappingLayout::LogQuadratic,
+            "LogCubic" => IndexMappingLayout::LogCubic,
+            "LogQuartic" => IndexMappingLayout::LogQuartic,
+            _ => return Err(Error::InvalidArgument("Unknown index mapping layout.")),
+        };
+        let gamma = mapping.get("gamma")?.as_f64()?;
+        relative_accuracy_from_gamma(gamma)?;
+        let index_offset = mapping.get("index_offset")?.as_f64()?;
+        if !index_offset.is_finite() {
+            return Err(Error::InvalidArgument("The index offset must be finite."));
+        }
+        let index_mapping = IndexMapping::with_gamma_offset(layout, gamma, index_offset)?;
+
+        let positive_value_store = json_store(json.get("positive")?, &index_mapping)?;
+        let negative_value_store = json_store(json.get("negative")?, &index_mapping)?;
+        let zero_count = json.get("zero_count")?.as_f64()?;
+        DDSketch::from_parts(
+            index_mapping,
+            positive_value_store,
+            negative_value_store,
+            zero_count,
+        )
+    }
+}
+
+fn json_store(bins: &Json, index_mapping: &IndexMapping) -> Result<Box<dyn Store>, Error> {
+    // Checked before adding the bins, so that far apart indexes cannot grow the store unbounded.
+    let min_index = index_mapping.index(index_mapping.min_indexable_value());
+    let max_index = index_mapping.index(index_mapping.max_indexable_value());
+    let mut store = UnboundedSizeDenseStore::new();
+    for bin in bins.as_array()? {
+        let index = bin.get("index")?.as_f64()?;
+        if index.fract() != 0.0 || index < min_index as f64 || index > max_index as f64 {
+            return Err(Error::InvalidArgument(
+                "The bin index must be an integer within the range of the index mapping.",
+            ));
+        }
+        let count = bin.get("count")?.as_f64()?;
+        if !count.is_finite() || count < 0.0 {
+            return Err(Error::InvalidArgument(
+                "The bin count must be finite and non-negative.",
+            ));
+        }
+        store.add(index as i32, count);
+    }
+    Ok(Box::new(store))
+}
+
+// Formats an f64 as a JSON number, or as null if it is not finite.
+struct JsonNumber(f64);
+
+impl fmt::Display for JsonNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_finite() {
+            write!(f, "{:?}", self.0)
+        } else {
+            f.write_str("null")
+        }
+    }
+}
+
+enum Json {
+    Null,
+    Bool,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Result<&Json, Error> {
+        match self {
+            Json::Object(members) => members.get(key).ok_or(INVALID_JSON),
+            _ => Err(INVALID_JSON),
+        }
+    }
+
+    fn as_f64(&self) -> Result<f64, Error> {
+        match self {
+            Json::Number(number) => Ok(*number),
+            _ => Err(INVALID_JSON),
+        }
+    }
+
+    fn as_str(&self) -> Result<&str, Error> {
+        match self {
+            Json::String(string) => Ok(string),
+            _ => Err(INVALID_JSON),
+        }
+    }
+
+    fn as_array(&self) -> Result<&[Json], Error> {
+        match self {
+            Json::Array(elements) => Ok(elements),
+            _ => Err(INVALID_JSON),
+        }
+    }
+}
+
+// A minimal parser for the JSON produced by `to_json`, string escapes are not supported.
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Result<u8, Error> {
+        self.skip_whitespace();
+        self.bytes.get(self.position).copied().ok_or(INVALID_JSON)
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        if self.peek()? != byte {
+            return Err(INVALID_JSON);
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn parse_keyword(&mut self, keyword: &[u8], value: Json) -> Result<Json, Error> {
+        if !self.bytes[self.position..].starts_with(keyword) {
+            return Err(INVALID_JSON);
+        }
+        self.position += keyword.len();
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<Json, Error> {
+        match self.peek()? {
+            b'{' => self.parse_nested(Parser::parse_object),
+            b'[' => self.parse_nested(Parser::parse_array),
+            b'"' => Ok(Json::String(self.parse_string()?)),
+            b'n' => self.parse_keyword(b"null", Json::Null),
+            b't' => self.parse_keyword(b"true", Json::Bool),
+            b'f' => self.parse_keyword(b"false", Json::Bool),
+            _ => self.parse_number(),
+        }
+    }
+
+    fn parse_nested(&mut self, parse: fn(&mut Self) -> Result<Json, Error>) -> Result<Json, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(Error::InvalidArgument("The JSON is nested too deeply."));
+        }
+        self.depth += 1;
+        let json = parse(self);
+        self.depth -= 1;
+        json
+    }
+
+    fn parse_object(&mut self) -> Result<Json, Error> {
+        self.expect(b'{')?;
+        let mut members = BTreeMap::new();
+        if self.peek()? == b'}' {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(b':')?;
+            members.insert(key, self.parse_value()?);
+            match self.peek()? {
+                b',' => self.position += 1,
+                b'}' => {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(INVALID_JSON),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, Error> {
+        self.expect(b'[')?;
+        let mut elements = Vec::new();
+        if self.peek()? == b']' {
+            self.position += 1;
+            return Ok(Json::Array(elements));
+        }
+        loop {
+            elements.push(self.parse_value()?);
+            match self.peek()? {
+                b',' => self.position += 1,
+                b']' => {
+                    self.position += 1;
+                    return Ok(Json::Array(elements));
+                }
+                _ => return Err(INVALID_JSON),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, Error> {
+        self.expect(b'"')?;
+        let start = self.position;
+        while self.position < self.bytes.len() {
+            match self.bytes[self.position] {
+                b'"' => {
+                    let string = std::str::from_utf8(&self.bytes[start..self.position])
+                        .map_err(|_| INVALID_JSON)?;
+                    self.position += 1;
+                    return Ok(string.to_string());
+                }
+                b'\\' => return Err(INVALID_JSON),
+                _ => self.position += 1,
+            }
+        }
+        Err(INVALID_JSON)
+    }
+
+    fn parse_number(&mut self) -> Result<Json, Error> {
+        let start = self.position;
+        while self.position < self.bytes.len()
+            && matches!(
+                self.bytes[self.position],
+                b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E'
+            )
+        {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .ok()
+            .and_then(|number| number.parse::<f64>().ok())
+            .map(Json::Number)
+            .ok_or(INVALID_JSON)
+    }
+}
@@ -30,7 +30,7 @@ Also you could merge other DDSketch:
     d2.accept(2.0);
     d2.accept(3.0);
     assert_eq!(3.0,  d2.get_count());
-    d2.merge_with(&mut d1).unwrap();
+    d2.merge_with(&d1).unwrap();
     assert_eq!(6.0,  d2.get_count());
 ```
 
@@ -46,6 +46,17 @@ Encode to bytes:
     println!("encode: {:?}", d.encode().unwrap());
 ```
 
+The index mapping, store and error types are exported from the crate root:
+```rust
+    use self::sketches_rust::{DDSketch, Error, IndexMapping, IndexMappingLayout, Store};
+    let mapping = IndexMapping::with_gamma_offset(IndexMappingLayout::LOG, 1.02, 0.0).unwrap();
+    let mut d = DDSketch::unbounded_dense(0.01).unwrap();
+    d.accept(1.0);
+    assert!(d.positive_value_store.get_total_count() == 1.0);
+    assert!(mapping.index(1.0) == 0);
+    assert!(matches!(DDSketch::unbounded_dense(2.0), Err(Error::RelativeAccuracyOutOfRange)));
+```
+
 Decode from bytes:
 ```rust
     use self::sketches_rust::{DDSketch};
@@ -61,16 +72,33 @@ Decode from bytes:
 
 mod error;
 mod index_mapping;
-mod input;
-mod output;
+pub mod input;
+mod json;
+mod otel;
+pub mod output;
 pub mod protos;
-mod serde;
+pub mod serde;
 mod sketch;
 mod store;
+mod windowed;
 
 pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
 }
 
 pub use self::error::Error;
-pub use self::sketch::DDSketch;
+pub use self::index_mapping::{
+    gamma_from_relative_accuracy, relative_accuracy_from_gamma, IndexMapping, IndexMappingLayout,
+};
+pub use self::otel::{OtelBuckets, OtelExponentialHistogram};
+#[cfg(feature = "test-utils")]
+pub use self::sketch::assert_quantile_within;
+pub use self::sketch::{
+    query_combined_quantile, CollapseDirection, DDSketch, DecodeLimits, MergeReport, Preset,
+    QuantileSketch, Region, SketchSummary, Summary,
+};
+pub use self::store::{
+    AdaptiveStore, Bin, BinEncodingMode, CollapsingHighestDenseStore, CollapsingLowestDenseStore,
+    Store, StoreIter, UnboundedSizeDenseStore, UnboundedSizeDenseU64Store,
+};
+pub use self::windowed::TimeWindowedSketch;
@@ -0,0 +1,128 @@
+use crate::error::Error;
+use crate::index_mapping::{IndexMapping, IndexMappingLayout};
+use crate::store::{Store, UnboundedSizeDenseStore};
+use crate::DDSketch;
+use std::collections::BTreeMap;
+
+const OTEL_MIN_SCALE: i32 = -10;
+const OTEL_MAX_SCALE: i32 = 20;
+
+/// The buckets of one sign of an `OtelExponentialHistogram`: `bucket_counts[i]` counts the
+/// absolute values in `(base^(offset + i), base^(offset + i + 1)]`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OtelBuckets {
+    pub offset: i32,
+    pub bucket_counts: Vec<u64>,
+}
+
+/// An OpenTelemetry exponential histogram data point, whose bucket boundaries are powers of
+/// `base = 2^(2^-scale)`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OtelExponentialHistogram {
+    pub scale: i32,
+    pub count: u64,
+    pub sum: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub zero_count: u64,
+    pub zero_threshold: f64,
+    pub positive: OtelBuckets,
+    pub negative: OtelBuckets,
+}
+
+impl DDSketch {
+    /// Converts the sketch to an OpenTelemetry exponential histogram, with the smallest scale
+    /// whose base is at most the gamma of the mapping. The bins are re-binned by their
+    /// representative values, and the counts are rounded to integers as OpenTelemetry requires.
+    pub fn to_otel_exponential_histogram(&self) -> OtelExponentialHistogram {
+        let scale = (std::f64::consts::LN_2 / self.index_mapping.gamma().ln())
+            .log2()
+            .ceil()
+            .clamp(OTEL_MIN_SCALE as f64, OTEL_MAX_SCALE as f64) as i32;
+        let scale_factor = 2f64.powi(scale);
+
+        let to_buckets = |store: &dyn Store| {
+            let mut counts = BTreeMap::new();
+            for bin in store.get_ascending_iter() {
+                let value = self.index_mapping.value(bin.index);
+                let index = (value.log2() * scale_factor).ceil() as i32 - 1;
+                *counts.entry(index).or_insert(0.0) += bin.count;
+            }
+            match (counts.keys().next(), counts.keys().next_back()) {
+                (Some(&offset), Some(&max_index)) => {
+                    let mut bucket_counts = vec![0; (max_index - offset + 1) as usize];
+                    for (index, count) in counts {
+                        bucket_counts[(index - offset) as usize] = count.round() as u64;
+                    }
+                    OtelBuckets {
+                        offset,
+                        bucket_counts,
+                    }
+                }
+                _ => OtelBuckets::default(),
+            }
+        };
+        let positive = to_buckets(self.positive_value_store.as_ref());
+        let negative = to_buckets(self.negative_value_store.as_ref());
+        let zero_count = self.zero_count.round() as u64;
+        let count = zero_count
+            + positive.bucket_counts.iter().sum::<u64>()
+            + negative.bucket_counts.iter().sum::<u64>();
+
+        OtelExponentialHistogram {
+            scale,
+            count,
+            sum: self.get_sum(),
+            min: self.get_exact_min(),
+            max: self.get_exact_max(),
+            zero_count,
+            zero_threshold: self.min_indexed_value,
+            positive,
+            negative,
+        }
+    }
+
+    /// Converts an OpenTelemetry exponential histogram to a sketch with a logarithmic mapping
+    /// whose gamma is the base of the histogram, so that the buckets map to the bins one to one.
+    pub fn from_otel(histogram: &OtelExponentialHistogram) -> Result<DDSketch, Error> {
+        if !(OTEL_MIN_SCALE..=OTEL_MAX_SCALE).contains(&histogram.scale) {
+            return Err(Error::InvalidArgument("The scale is out of range."));
+        }
+        let zero_threshold = histogram.zero_threshold;
+        if zero_threshold.is_nan() || zero_threshold < 0.0 || zero_threshold.is_infinite() {
+            return Err(Error::InvalidArgument(
+                "The zero threshold must be finite and non-negative.",
+            ));
+        }
+        let gamma = 2f64.powf(2f64.powi(-histogram.scale));
+        let index_mapping = IndexMapping::with_gamma_offset(IndexMappingLayout::LOG, gamma, 0.0)?;
+
+        let min_index = index_mapping.index(index_mapping.min_indexable_value());
+        let max_index = index_mapping.index(index_mapping.max_indexable_value());
+
+        let from_buckets = |buckets: &OtelBuckets| -> Result<Box<dyn Store>, Error> {
+            let mut store = UnboundedSizeDenseStore::new();
+            for (i, count) in buckets.bucket_counts.iter().enumerate() {
+                let index = buckets.offset as i64 + i as i64;
+                if index < min_index as i64 || index > max_index as i64 {
+                    return Err(Error::InvalidArgument("The bucket index is out of range."));
+                }
+                if *count > 0 {
+                    store.add(index as i32, *count as f64);
+                }
+            }
+            Ok(Box::new(store))
+        };
+        let sketch = DDSketch::from_parts(
+            index_mapping,
+            from_buckets(&histogram.positive)?,
+            from_buckets(&histogram.negative)?,
+            histogram.zero_count as f64,
+        )?;
+        let mut sketch = sketch.with_zero_threshold(zero_threshold);
+        if let (Some(min), Some(max)) = (histogram.min, histogram.max) {
+            sketch.set_exact_extremes(min, max);
+        }
+        Ok(sketch)
+    }
+}
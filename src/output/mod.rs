@@ -11,6 +11,15 @@ impl Output {
         }
     }
 
+    /// Returns the number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
     pub fn trim(self) -> Vec<u8> {
         self.vec
     }
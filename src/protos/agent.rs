@@ -0,0 +1,121 @@
+use protobuf::Message;
+
+use crate::index_mapping::{IndexMapping, IndexMappingLayout};
+use crate::proto::agent_payload::sketch_payload::sketch::Dogsketch;
+use crate::store::UnboundedSizeDenseStore;
+use crate::{DDSketch, Error};
+
+// The default quantile configuration of the Datadog agent: keys are signed, 0 holds the values
+// smaller than AGENT_MIN_VALUE in absolute value, and positive keys index a logarithmic mapping.
+const AGENT_GAMMA: f64 = 1.0 + 2.0 / 128.0;
+const AGENT_MIN_VALUE: f64 = 1e-9;
+const AGENT_MAX_KEY: i32 = i16::MAX as i32;
+
+fn agent_bias() -> i32 {
+    -(AGENT_MIN_VALUE.ln() / AGENT_GAMMA.ln()).floor() as i32 + 1
+}
+
+fn agent_key(value: f64) -> i32 {
+    if value < 0.0 {
+        return -agent_key(-value);
+    }
+    if value < AGENT_MIN_VALUE {
+        return 0;
+    }
+    let key = (value.ln() / AGENT_GAMMA.ln() + 0.5).floor() as i32 + agent_bias();
+    key.clamp(1, AGENT_MAX_KEY)
+}
+
+fn agent_value(key: i32) -> f64 {
+    if key < 0 {
+        return -agent_value(-key);
+    }
+    if key == 0 {
+        return 0.0;
+    }
+    AGENT_GAMMA.powi(key - agent_bias())
+}
+
+impl DDSketch {
+    /// Encodes the sketch as the `Dogsketch` message of the Datadog agent payload, whose bins use
+    /// the fixed mapping of the agent. The counts of the bins that fall in the same key are summed,
+    /// then rounded to the nearest integer and capped at `u32::MAX`. The min, max, sum and average
+    /// are those of the sketch, so that the backend does not recompute them from the bins.
+    pub fn to_agent_payload(&self) -> Vec<u8> {
+        let negative_bins = self
+            .negative_value_store
+            .get_descending_iter()
+            .map(|bin| (-self.index_mapping.value(bin.index), bin.count));
+        let positive_bins = self
+            .positive_value_store
+            .get_ascending_iter()
+            .map(|bin| (self.index_mapping.value(bin.index), bin.count));
+
+        // Adjacent bins of the sketch can share a key of the coarser agent mapping: merge their
+        // counts before rounding, so that every key is written once.
+        let mut bins: Vec<(i32, f64, f64)> = Vec::new();
+        for (value, bin_count) in negative_bins
+            .chain(std::iter::once((0.0, self.zero_count)))
+            .chain(positive_bins)
+        {
+            if bin_count <= 0.0 {
+                continue;
+            }
+            let key = agent_key(value);
+            match bins.last_mut() {
+                Some((last_key, _, last_count)) if *last_key == key => *last_count += bin_count,
+                _ => bins.push((key, value, bin_count)),
+            }
+        }
+
+        let mut dogsketch = Dogsketch::new();
+        let mut count: f64 = 0.0;
+        let mut sum = 0.0;
+        for (key, value, bin_count) in bins {
+            let n = bin_count.round().min(u32::MAX as f64) as u32;
+            if n == 0 {
+                continue;
+            }
+            dogsketch.k.push(key);
+            dogsketch.n.push(n);
+            if count == 0.0 {
+                dogsketch.min = value;
+            }
+            dogsketch.max = value;
+            count += n as f64;
+            sum += value * n as f64;
+        }
+
+        if count > 0.0 {
+            dogsketch.cnt = count as i64;
+            dogsketch.min = self.get_exact_min().unwrap_or(dogsketch.min);
+            dogsketch.max = self.get_exact_max().unwrap_or(dogsketch.max);
+            dogsketch.sum = self.get_sum().unwrap_or(sum);
+            dogsketch.avg = self.get_average().unwrap_or(sum / count);
+        }
+        dogsketch.write_to_bytes().unwrap()
+    }
+
+    /// Decodes a `Dogsketch` message of the Datadog agent payload into a sketch using the
+    /// logarithmic mapping of the agent.
+    pub fn from_agent_payload(bytes: &[u8]) -> Result<DDSketch, Error> {
+        let dogsketch = Dogsketch::parse_from_bytes(bytes)
+            .map_err(|_| Error::InvalidArgument("Invalid agent payload."))?;
+        if dogsketch.k.len() != dogsketch.n.len() {
+            return Err(Error::InvalidArgument(
+                "The agent payload keys and counts must have the same length.",
+            ));
+        }
+
+        let index_mapping = IndexMapping::with_gamma(IndexMappingLayout::LOG, AGENT_GAMMA)?;
+        let mut sketch = DDSketch::new(
+            index_mapping,
+            Box::new(UnboundedSizeDenseStore::new()),
+            Box::new(UnboundedSizeDenseStore::new()),
+        );
+        for (key, n) in dogsketch.k.iter().zip(dogsketch.n.iter()) {
+            sketch.accept_with_count(agent_value(*key), *n as f64);
+        }
+        Ok(sketch)
+    }
+}
@@ -2,8 +2,8 @@ use protobuf::{EnumOrUnknown, MessageField, SpecialFields};
 
 use crate::index_mapping::{IndexMapping, IndexMappingLayout};
 use crate::proto::ddsketch::index_mapping::Interpolation;
-use crate::store::{Store, UnboundedSizeDenseStore};
-use crate::{proto, DDSketch};
+use crate::store::{Bin, Store, UnboundedSizeDenseStore};
+use crate::{proto, relative_accuracy_from_gamma, DDSketch, Error};
 
 impl From<MessageField<proto::ddsketch::IndexMapping>> for IndexMapping {
     fn from(proto: MessageField<proto::ddsketch::IndexMapping>) -> Self {
@@ -74,18 +74,57 @@ impl From<MessageField<proto::ddsketch::Store>> for UnboundedSizeDenseStore {
     }
 }
 
+impl UnboundedSizeDenseStore {
+    /// Converts a protobuf store like `From`, but fails if its bins would overflow the index range
+    /// or span more than `max_bins` indexes, which bounds the memory of the dense store.
+    pub fn try_from_proto(
+        proto: &MessageField<proto::ddsketch::Store>,
+        max_bins: usize,
+    ) -> Result<Self, Error> {
+        let mut min_index = i64::MAX;
+        let mut max_index = i64::MIN;
+        for index in proto.binCounts.keys() {
+            min_index = min_index.min(*index as i64);
+            max_index = max_index.max(*index as i64);
+        }
+        if !proto.contiguousBinCounts.is_empty() {
+            let index_offset = proto.contiguousBinIndexOffset as i64;
+            let last_index = index_offset + proto.contiguousBinCounts.len() as i64 - 1;
+            if last_index > i32::MAX as i64 {
+                return Err(Error::InvalidArgument(
+                    "The contiguous bins overflow the index range.",
+                ));
+            }
+            min_index = min_index.min(index_offset);
+            max_index = max_index.max(last_index);
+        }
+        if min_index <= max_index && max_index - min_index >= max_bins as i64 {
+            return Err(Error::InvalidArgument("The store exceeds the bin limit."));
+        }
+        Ok(proto.clone().into())
+    }
+}
+
 impl From<Box<dyn Store>> for MessageField<proto::ddsketch::Store> {
     fn from(value: Box<dyn Store>) -> Self {
         let mut proto_store = proto::ddsketch::Store::new();
 
         if !value.is_empty() {
-            proto_store.contiguousBinIndexOffset = value.get_min_index();
-            let mut i = value.get_min_index() - value.get_offset();
-            let limit = value.get_max_index() - value.get_offset();
-
-            while i <= limit {
-                proto_store.contiguousBinCounts.push(value.get_count(i));
-                i += 1;
+            // Export the populated bins as a map when that is more compact, and otherwise
+            // contiguously from the store offset, so that sparse stores are supported too.
+            let bins: Vec<Bin> = value.get_ascending_iter().collect();
+            let span = value.get_max_index() as i64 - value.get_min_index() as i64 + 1;
+            if 2 * bins.len() as i64 <= span {
+                proto_store
+                    .binCounts
+                    .extend(bins.iter().map(|bin| (bin.index, bin.count)));
+            } else {
+                let min_index = value.get_min_index();
+                proto_store.contiguousBinIndexOffset = min_index;
+                proto_store.contiguousBinCounts = vec![0.0; span as usize];
+                for bin in bins {
+                    proto_store.contiguousBinCounts[(bin.index - min_index) as usize] = bin.count;
+                }
             }
         }
 
@@ -99,14 +138,49 @@ impl From<proto::ddsketch::DDSketch> for DDSketch {
         let negative_value_store: UnboundedSizeDenseStore = proto.negativeValues.into();
         let positive_value_store: UnboundedSizeDenseStore = proto.positiveValues.into();
 
-        DDSketch {
-            index_mapping: index_mapping.clone(),
-            min_indexed_value: f64::max(0.0, index_mapping.min_indexable_value()),
-            max_indexed_value: index_mapping.max_indexable_value(),
-            negative_value_store: Box::from(negative_value_store),
-            positive_value_store: Box::from(positive_value_store),
-            zero_count: proto.zeroCount,
+        let mut sketch = DDSketch::new(
+            index_mapping,
+            Box::from(negative_value_store),
+            Box::from(positive_value_store),
+        );
+        sketch.zero_count = proto.zeroCount;
+        sketch
+    }
+}
+
+impl DDSketch {
+    /// Converts a protobuf sketch like `From`, but reports an invalid index mapping as an error
+    /// and bounds each store with `UnboundedSizeDenseStore::try_from_proto`.
+    pub fn try_from_proto(
+        proto: &proto::ddsketch::DDSketch,
+        max_bins: usize,
+    ) -> Result<Self, Error> {
+        let layout = match proto.mapping.interpolation.enum_value() {
+            Ok(Interpolation::NONE) => IndexMappingLayout::LOG,
+            Ok(Interpolation::CUBIC) => IndexMappingLayout::LogCubic,
+            _ => return Err(Error::InvalidArgument("Unsupported interpolation type.")),
+        };
+        relative_accuracy_from_gamma(proto.mapping.gamma)?;
+        if !proto.mapping.indexOffset.is_finite() {
+            return Err(Error::InvalidArgument("The index offset must be finite."));
         }
+        let index_mapping = IndexMapping::with_gamma_offset(
+            layout,
+            proto.mapping.gamma,
+            proto.mapping.indexOffset,
+        )?;
+        let negative_value_store =
+            UnboundedSizeDenseStore::try_from_proto(&proto.negativeValues, max_bins)?;
+        let positive_value_store =
+            UnboundedSizeDenseStore::try_from_proto(&proto.positiveValues, max_bins)?;
+
+        let mut sketch = DDSketch::new(
+            index_mapping,
+            Box::new(negative_value_store),
+            Box::new(positive_value_store),
+        );
+        sketch.zero_count = proto.zeroCount;
+        Ok(sketch)
     }
 }
 
@@ -127,3 +201,26 @@ impl From<DDSketch> for proto::ddsketch::DDSketch {
         }
     }
 }
+
+impl DDSketch {
+    /// Returns whether `other` holds the same bins as this sketch, up to `epsilon` on the counts,
+    /// once converted to the protobuf format and back. Unlike `approx_eq`, the gamma and index
+    /// offset of the mappings only need to match up to a relative `epsilon`, which accommodates the
+    /// parameters derived by other implementations.
+    pub fn proto_roundtrip_equivalent(&self, other: &DDSketch, epsilon: f64) -> bool {
+        let approx_eq = |a: f64, b: f64| (a - b).abs() <= epsilon * f64::max(a.abs(), b.abs());
+        self.index_mapping.layout() == other.index_mapping.layout()
+            && approx_eq(self.index_mapping.gamma(), other.index_mapping.gamma())
+            && approx_eq(
+                self.index_mapping.index_offset(),
+                other.index_mapping.index_offset(),
+            )
+            && (self.zero_count - other.zero_count).abs() <= epsilon
+            && self
+                .negative_value_store
+                .approx_eq(other.negative_value_store.as_ref(), epsilon)
+            && self
+                .positive_value_store
+                .approx_eq(other.positive_value_store.as_ref(), epsilon)
+    }
+}
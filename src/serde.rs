@@ -25,6 +25,8 @@ pub fn decode_signed_var_long(input: &mut Input) -> Result<i64, Error> {
     Ok(zig_zag_decode(decode_unsigned_var_long(input)?))
 }
 
+// Var-longs and var-doubles are self-bounding: their 9th byte carries 8 bits rather than 7 bits and
+// a continuation bit, so decoding stops there even if every byte has the continuation bit set.
 pub fn decode_unsigned_var_long(input: &mut Input) -> Result<i64, Error> {
     let mut value: i64 = 0;
     let mut shift = 0;
@@ -162,6 +164,22 @@ pub fn encode_signed_var_long(output: &mut Output, value: i64) -> Result<(), Err
     encode_unsigned_var_long(output, zig_zag_encode(value))
 }
 
+pub fn encode_var_long(output: &mut Output, value: i64) -> Result<(), Error> {
+    encode_signed_var_long(output, value)
+}
+
+pub fn decode_var_long(input: &mut Input) -> Result<i64, Error> {
+    decode_signed_var_long(input)
+}
+
+pub fn encode_var_u64(output: &mut Output, value: u64) -> Result<(), Error> {
+    encode_unsigned_var_long(output, value as i64)
+}
+
+pub fn decode_var_u64(input: &mut Input) -> Result<u64, Error> {
+    Ok(decode_unsigned_var_long(input)? as u64)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -500,4 +518,80 @@ mod tests {
     fn test_build_double() {
         assert_eq!(build_double(0, 1.0), 1.0);
     }
+
+    #[test]
+    fn test_var_long_round_trip() {
+        let values = [
+            i64::MIN,
+            i64::MIN + 1,
+            -4611686018427387905,
+            -65536,
+            -1,
+            0,
+            1,
+            127,
+            128,
+            65536,
+            4611686018427387904,
+            i64::MAX - 1,
+            i64::MAX,
+        ];
+        for value in values {
+            let mut output = Output::with_capacity(32);
+            encode_var_long(&mut output, value).unwrap();
+            let bytes = output.trim();
+            assert_eq!(signed_var_long_encoded_length(value) as usize, bytes.len());
+            let mut input = Input::wrap(&bytes);
+            assert_eq!(value, decode_var_long(&mut input).unwrap());
+            assert!(!input.has_remaining());
+        }
+    }
+
+    #[test]
+    fn test_decode_var_length_is_self_bounding() {
+        let bytes = [0xff; 20];
+        let mut input = Input::wrap(&bytes);
+        assert_eq!(u64::MAX, decode_var_u64(&mut input).unwrap());
+        input.skip(11).unwrap();
+        assert!(!input.has_remaining());
+        let mut input = Input::wrap(&bytes);
+        decode_var_double(&mut input).unwrap();
+        input.skip(11).unwrap();
+        assert!(!input.has_remaining());
+
+        let truncated = [0xff; 8];
+        assert!(matches!(
+            decode_unsigned_var_long(&mut Input::wrap(&truncated)),
+            Err(Error::IoError(_))
+        ));
+        assert!(matches!(
+            decode_var_double(&mut Input::wrap(&truncated)),
+            Err(Error::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_var_u64_round_trip() {
+        let values = [
+            0,
+            1,
+            127,
+            128,
+            16383,
+            16384,
+            u32::MAX as u64,
+            i64::MAX as u64,
+            i64::MAX as u64 + 1,
+            u64::MAX - 1,
+            u64::MAX,
+        ];
+        for value in values {
+            let mut output = Output::with_capacity(32);
+            encode_var_u64(&mut output, value).unwrap();
+            let bytes = output.trim();
+            let mut input = Input::wrap(&bytes);
+            assert_eq!(value, decode_var_u64(&mut input).unwrap());
+            assert!(!input.has_remaining());
+        }
+    }
 }
@@ -5,9 +5,22 @@ use crate::input::Input;
 use crate::output::Output;
 use crate::serde;
 use crate::store::{
-    BinEncodingMode, CollapsingHighestDenseStore, CollapsingLowestDenseStore, Store,
-    UnboundedSizeDenseStore,
+    merge_store, AdaptiveStore, Bin, BinEncodingMode, CollapsingHighestDenseStore,
+    CollapsingLowestDenseStore, Store, StoreIter, UnboundedSizeDenseStore,
+    UnboundedSizeDenseU64Store,
 };
+use std::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::io::{BufRead, BufReader, Read, Write};
+use std::mem;
+
+#[cfg(feature = "flate2")]
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const ACCEPT_BATCH_SIZE: usize = 256;
+// Native encodings lead with an index mapping flag, which never takes this value.
+const VERSIONED_MAGIC: u8 = 0xdd;
+const FORMAT_VERSION: u8 = 1;
 
 pub struct DDSketch {
     pub index_mapping: IndexMapping,
@@ -16,6 +29,117 @@ pub struct DDSketch {
     pub negative_value_store: Box<dyn Store>,
     pub positive_value_store: Box<dyn Store>,
     pub zero_count: f64,
+    // Sum of the representative values of the bins at the time the values were added to them.
+    sum: f64,
+    // Extremes of the accepted values, infinite while none was accepted.
+    exact_min: f64,
+    exact_max: f64,
+    // Whether values of unknown extremes were recorded, e.g. merged or decoded without them. This
+    // sticks until the sketch is cleared, as accepting more values cannot make them known again.
+    unknown_extremes: bool,
+    accept_min: f64,
+    accept_max: f64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SketchSummary {
+    pub count: Option<f64>,
+    pub sum: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub zero_count: f64,
+}
+
+/// How the counts of a sketch merged with `merge_with_report` were recorded.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MergeReport {
+    /// The count of the bins that were folded into a collapsed edge bin during the merge.
+    pub collapsed_count: f64,
+    /// The count that the stores could not record at all.
+    pub dropped_out_of_range: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub count: f64,
+    pub sum: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub quantiles: Vec<Option<f64>>,
+}
+
+/// Which end of the value range loses accuracy once a bounded sketch reaches its maximum number
+/// of bins: the lowest values, the highest values, or both tails of the range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollapseDirection {
+    Lowest,
+    Highest,
+    Both,
+}
+
+/// Where `DDSketch::accept` records a value: in the negative store, in the zero bucket, in the
+/// positive store, or nowhere because it is out of the accept bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Negative,
+    Zero,
+    Positive,
+    OutOfRange,
+}
+
+/// The operations shared by quantile sketches, for code that is generic over the sketch type.
+///
+/// ```rust
+/// use sketches_rust::{DDSketch, QuantileSketch};
+///
+/// fn median<T: QuantileSketch>(sketch: &mut T, values: &[f64]) -> Option<f64> {
+///     for value in values {
+///         sketch.accept(*value);
+///     }
+///     sketch.get_value_at_quantile(0.5)
+/// }
+///
+/// let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+/// let median = median(&mut sketch, &[1.0, 2.0, 3.0]).unwrap();
+/// assert!((median - 2.0).abs() <= 2.0 * 0.01);
+/// assert_eq!(3.0, QuantileSketch::get_count(&sketch));
+/// ```
+pub trait QuantileSketch {
+    fn accept(&mut self, value: f64);
+    fn get_value_at_quantile(&self, quantile: f64) -> Option<f64>;
+    fn get_count(&self) -> f64;
+    fn merge_with(&mut self, other: &Self) -> Result<(), Error>;
+}
+
+/// Bounds on the resources `DDSketch::decode_limited` may use: the size of the input and the
+/// number of indexes the bins of each store may span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    pub max_bins: usize,
+    pub max_bytes: usize,
+}
+
+/// Common relative accuracies, for which sketches can be built without error handling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// A relative accuracy of 0.1%.
+    Fine,
+    /// A relative accuracy of 1%.
+    Standard,
+    /// A relative accuracy of 2%.
+    Coarse,
+}
+
+impl Preset {
+    /// Returns the relative accuracy of the preset, as passed to `DDSketch::unbounded_dense`.
+    pub fn relative_accuracy(self) -> f64 {
+        match self {
+            Preset::Fine => 0.001,
+            Preset::Standard => 0.01,
+            Preset::Coarse => 0.02,
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -36,23 +160,158 @@ impl DDSketch {
     }
 
     pub fn accept_with_count(&mut self, value: f64, count: f64) {
+        self.accept_with_index(value, count, None);
+    }
+
+    // Records `value` given the index of its absolute value if it is already known, otherwise the
+    // index is only computed if the value falls into one of the stores.
+    fn accept_with_index(&mut self, value: f64, count: f64, index: Option<i32>) {
         if count < 0.0 {
             return;
         }
+        self.record(value, count, self.region_of(value), index);
+    }
 
-        if value < -self.max_indexed_value || value > self.max_indexed_value {
+    // Records `value` with a non-negative `count` into `region`, see `accept_with_index`.
+    fn record(&mut self, value: f64, count: f64, region: Region, index: Option<i32>) {
+        if region == Region::OutOfRange {
             return;
         }
 
-        if value > self.min_indexed_value {
-            self.positive_value_store
-                .add(self.index_mapping.index(value), 1.0);
-        } else if value < -self.min_indexed_value {
-            self.negative_value_store
-                .add(self.index_mapping.index(-value), 1.0);
+        // Record -0.0 as 0.0 so that it does not surface in the exact minimum and maximum.
+        let value = if value == 0.0 { 0.0 } else { value };
+        // Account for the count the store keeps, so that the sum and the extremes match the bins.
+        let count = match region {
+            Region::Positive => self.positive_value_store.stored_count(count),
+            Region::Negative => self.negative_value_store.stored_count(count),
+            Region::Zero | Region::OutOfRange => count,
+        };
+        if count > 0.0 {
+            self.exact_min = f64::min(self.exact_min, value);
+            self.exact_max = f64::max(self.exact_max, value);
+        }
+
+        match region {
+            Region::Positive => {
+                let index = index.unwrap_or_else(|| self.index_mapping.index(value));
+                self.positive_value_store.add(index, count);
+                self.sum += self.index_mapping.value(index) * count;
+            }
+            Region::Negative => {
+                let index = index.unwrap_or_else(|| self.index_mapping.index(-value));
+                self.negative_value_store.add(index, count);
+                self.sum -= self.index_mapping.value(index) * count;
+            }
+            Region::Zero => self.zero_count += count,
+            Region::OutOfRange => {}
+        }
+    }
+
+    /// Returns where `accept` would record `value`, without recording it. Values beyond the accept
+    /// bounds, which default to `max_indexed_value` in absolute value, are out of range. Like in
+    /// `accept`, NaN falls into the zero bucket.
+    pub fn region_of(&self, value: f64) -> Region {
+        if value < self.accept_min || value > self.accept_max {
+            Region::OutOfRange
+        } else {
+            self.region_within_bounds(value)
+        }
+    }
+
+    // Classifies a value that is within the accept bounds by its sign and the zero band only.
+    fn region_within_bounds(&self, value: f64) -> Region {
+        if value >= self.min_indexed_value {
+            Region::Positive
+        } else if value <= -self.min_indexed_value {
+            Region::Negative
         } else {
-            self.zero_count += 1.0;
+            Region::Zero
+        }
+    }
+
+    /// Records `value` with `count` like `accept_with_count`, without checking the count nor the
+    /// accept bounds. The caller must ensure that `value` is finite and within the accept bounds
+    /// and that `count` is non-negative, this is only checked in debug builds.
+    pub fn accept_unchecked(&mut self, value: f64, count: f64) {
+        debug_assert!(count >= 0.0, "The count must be non-negative.");
+        debug_assert!(
+            value >= self.accept_min && value <= self.accept_max,
+            "The value must be within the accept bounds."
+        );
+        self.record(value, count, self.region_within_bounds(value), None);
+    }
+
+    /// Records all the values, indexing them in batches with `IndexMapping::index_batch`.
+    pub fn accept_many(&mut self, values: &[f64]) {
+        let mut magnitudes = [0.0; ACCEPT_BATCH_SIZE];
+        let mut indexes = [0; ACCEPT_BATCH_SIZE];
+        for values in values.chunks(ACCEPT_BATCH_SIZE) {
+            let magnitudes = &mut magnitudes[..values.len()];
+            let indexes = &mut indexes[..values.len()];
+            for (magnitude, value) in magnitudes.iter_mut().zip(values) {
+                *magnitude = value.abs();
+            }
+            self.index_mapping.index_batch(magnitudes, indexes);
+            for (&value, &index) in values.iter().zip(indexes.iter()) {
+                self.accept_with_index(value, 1.0, Some(index));
+            }
+        }
+    }
+
+    /// Records each value of `values` with the count at the same position in `counts`.
+    pub fn accept_many_with_counts(&mut self, values: &[f64], counts: &[f64]) -> Result<(), Error> {
+        if values.len() != counts.len() {
+            return Err(Error::InvalidArgument(
+                "The values and the counts must have the same length.",
+            ));
+        }
+        for (&value, &count) in values.iter().zip(counts) {
+            self.accept_with_count(value, count);
+        }
+        Ok(())
+    }
+
+    /// Records `value` like `accept`, but reports an error instead of ignoring it when it is NaN
+    /// or out of the accept bounds, which default to `max_indexed_value` in absolute value.
+    pub fn try_accept(&mut self, value: f64) -> Result<(), Error> {
+        if value.is_nan() || self.region_of(value) == Region::OutOfRange {
+            return Err(Error::InvalidArgument(
+                "The value is NaN or out of the accept bounds.",
+            ));
+        }
+        self.accept(value);
+        Ok(())
+    }
+
+    /// Returns the index of the bin of the positive or negative store `accept` would record `value`
+    /// in, or None if it falls into the zero bucket, as NaN does, or out of the accept bounds.
+    pub fn index_of(&self, value: f64) -> Option<i32> {
+        match self.region_of(value) {
+            Region::Positive | Region::Negative => Some(self.index_mapping.index(value.abs())),
+            Region::Zero | Region::OutOfRange => None,
+        }
+    }
+
+    /// Returns the representative value of the bin at `index` of the negative store if `negative`,
+    /// or of the positive store otherwise.
+    pub fn value_of_index(&self, index: i32, negative: bool) -> f64 {
+        let value = self.index_mapping.value(index);
+        if negative {
+            -value
+        } else {
+            value
+        }
+    }
+
+    pub fn set_accept_bounds(&mut self, min: f64, max: f64) -> Result<(), Error> {
+        if min.is_nan() || max.is_nan() || min > max {
+            return Err(Error::InvalidArgument(
+                "The accept bounds must be ordered and not NaN.",
+            ));
         }
+        self.accept_min = f64::max(min, -self.max_indexed_value);
+        self.accept_max = f64::min(max, self.max_indexed_value);
+        Ok(())
     }
 
     pub fn is_empty(&self) -> bool {
@@ -65,28 +324,237 @@ impl DDSketch {
         self.negative_value_store.clear();
         self.positive_value_store.clear();
         self.zero_count = 0.0;
+        self.sum = 0.0;
+        self.reset_exact_extremes();
+    }
+
+    /// Clears the sketch like `clear`, and also releases the memory allocated for the bins, which
+    /// is useful when reusing a sketch for values of a narrower range.
+    pub fn clear_and_shrink(&mut self) {
+        self.clear();
+        self.negative_value_store.clear_and_shrink();
+        self.positive_value_store.clear_and_shrink();
+    }
+
+    fn reset_exact_extremes(&mut self) {
+        self.exact_min = f64::INFINITY;
+        self.exact_max = f64::NEG_INFINITY;
+        self.unknown_extremes = false;
+    }
+
+    // Drops the extremes after the bins were changed in a way that does not track them.
+    fn forget_exact_extremes(&mut self) {
+        self.reset_exact_extremes();
+        self.unknown_extremes = !self.is_empty();
+    }
+
+    fn has_unknown_extremes(&self) -> bool {
+        self.unknown_extremes || (self.exact_min > self.exact_max && !self.is_empty())
+    }
+
+    /// Sets the extremes of values that were recorded without going through `accept`, e.g. when
+    /// decoding. They are unknown if `min > max` while the sketch is not empty.
+    pub(crate) fn set_exact_extremes(&mut self, min: f64, max: f64) {
+        self.exact_min = min;
+        self.exact_max = max;
+        self.unknown_extremes = min > max && !self.is_empty();
+    }
+
+    // Merges the extremes of values merged into the sketch, of which `min` and `max` are unknown
+    // if `min > max` while `count` is positive.
+    fn merge_exact_extremes(&mut self, min: f64, max: f64, count: f64) {
+        if min > max && count > 0.0 {
+            self.unknown_extremes = true;
+        }
+        self.exact_min = f64::min(self.exact_min, min);
+        self.exact_max = f64::max(self.exact_max, max);
+    }
+
+    fn merge_exact_extremes_of(&mut self, other: &DDSketch) {
+        if other.has_unknown_extremes() {
+            self.unknown_extremes = true;
+        }
+        self.exact_min = f64::min(self.exact_min, other.exact_min);
+        self.exact_max = f64::max(self.exact_max, other.exact_max);
+    }
+
+    /// Removes the bins whose count is below `min_count`. With `redistribute`, the count of each
+    /// removed bin is moved to the nearest retained bin of the same store (or to the largest bin if
+    /// none reaches `min_count`), so that the total count is preserved.
+    pub fn prune(&mut self, min_count: f64, redistribute: bool) {
+        prune_store(self.negative_value_store.as_mut(), min_count, redistribute);
+        prune_store(self.positive_value_store.as_mut(), min_count, redistribute);
+        self.sum = self.bins_sum();
+        self.forget_exact_extremes();
+    }
+
+    pub fn fresh(&self) -> DDSketch {
+        DDSketch {
+            index_mapping: self.index_mapping.clone(),
+            min_indexed_value: self.min_indexed_value,
+            max_indexed_value: self.max_indexed_value,
+            negative_value_store: self.negative_value_store.new_empty_like(),
+            positive_value_store: self.positive_value_store.new_empty_like(),
+            zero_count: 0.0,
+            sum: 0.0,
+            exact_min: f64::INFINITY,
+            exact_max: f64::NEG_INFINITY,
+            unknown_extremes: false,
+            accept_min: self.accept_min,
+            accept_max: self.accept_max,
+        }
+    }
+
+    pub fn drain(&mut self) -> DDSketch {
+        let negative_value_store = self.negative_value_store.new_empty_like();
+        let positive_value_store = self.positive_value_store.new_empty_like();
+        DDSketch {
+            index_mapping: self.index_mapping.clone(),
+            min_indexed_value: self.min_indexed_value,
+            max_indexed_value: self.max_indexed_value,
+            negative_value_store: mem::replace(
+                &mut self.negative_value_store,
+                negative_value_store,
+            ),
+            positive_value_store: mem::replace(
+                &mut self.positive_value_store,
+                positive_value_store,
+            ),
+            zero_count: mem::take(&mut self.zero_count),
+            sum: mem::take(&mut self.sum),
+            exact_min: mem::replace(&mut self.exact_min, f64::INFINITY),
+            exact_max: mem::replace(&mut self.exact_max, f64::NEG_INFINITY),
+            unknown_extremes: mem::take(&mut self.unknown_extremes),
+            accept_min: self.accept_min,
+            accept_max: self.accept_max,
+        }
+    }
+
+    /// Returns the smallest value accepted by the sketch, as opposed to `get_min` which returns
+    /// the representative value of its bin, or None if it is not known, e.g. after `prune` or
+    /// `subtract`, or once a sketch encoded or built without it was decoded or merged in.
+    pub fn get_exact_min(&self) -> Option<f64> {
+        (!self.unknown_extremes && self.exact_min <= self.exact_max).then_some(self.exact_min)
+    }
+
+    /// Returns the largest value accepted by the sketch, as opposed to `get_max` which returns
+    /// the representative value of its bin, or None if it is not known.
+    pub fn get_exact_max(&self) -> Option<f64> {
+        (!self.unknown_extremes && self.exact_min <= self.exact_max).then_some(self.exact_max)
     }
 
-    pub fn get_count(&mut self) -> f64 {
+    /// Returns the count of values recorded in the zero bucket, i.e. values whose absolute value
+    /// is smaller than `min_indexed_value`. Quantile queries report them as exactly `0.0`.
+    pub fn zero_count(&self) -> f64 {
+        self.zero_count
+    }
+
+    /// Returns the fraction of the total count held by the zero bucket, or `None` if the sketch is
+    /// empty. A high fraction suggests lowering the zero threshold.
+    pub fn zero_bucket_fraction(&self) -> Option<f64> {
+        let count = self.get_count();
+        (count > 0.0).then(|| self.zero_count / count)
+    }
+
+    /// Returns the smallest absolute value that is recorded in the stores; anything closer to zero
+    /// is counted in the zero bucket.
+    pub fn min_indexed_value(&self) -> f64 {
+        self.min_indexed_value
+    }
+
+    /// Returns the largest absolute value that can be recorded. It only depends on the index
+    /// mapping, not on the stores: beyond it the representative values of the bins would overflow,
+    /// so `accept` ignores larger values even on unbounded sketches.
+    pub fn max_indexable_value(&self) -> f64 {
+        self.max_indexed_value
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.zero_count.is_nan() || self.zero_count < 0.0 {
+            return Err(Error::InvalidArgument(
+                "The zero count must be non-negative.",
+            ));
+        }
+        let negative_bin_iterator = self.negative_value_store.get_ascending_iter();
+        let positive_bin_iterator = self.positive_value_store.get_ascending_iter();
+        for bin in negative_bin_iterator.chain(positive_bin_iterator) {
+            if bin.count.is_nan() || bin.count < 0.0 {
+                return Err(Error::InvalidArgument(
+                    "The bin counts must be non-negative.",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_count(&self) -> f64 {
         self.zero_count
             + self.negative_value_store.get_total_count()
             + self.positive_value_store.get_total_count()
     }
 
-    pub fn get_sum(&mut self) -> Option<f64> {
-        let count = self.get_count();
-        if count <= 0.0 {
+    /// Returns the total count rounded to the nearest integer, saturating at `0` and `u64::MAX`.
+    pub fn get_count_u64(&self) -> u64 {
+        // Float to integer casts saturate and map NaN to 0.
+        self.get_count().round() as u64
+    }
+
+    /// Returns the number of values at or below the quantile `quantile`, i.e. `quantile` times the
+    /// total count rounded like `get_count_u64`.
+    pub fn get_count_at_quantile_u64(&self, quantile: f64) -> Option<u64> {
+        if !(0.0..=1.0).contains(&quantile) {
             return None;
         }
+        Some((quantile * self.get_count()).round() as u64)
+    }
 
-        let mut sum = 0.0;
-        sum -= self.negative_value_store.get_sum(&self.index_mapping);
-        sum += self.positive_value_store.get_sum(&self.index_mapping);
+    /// Returns the sum of the representative values of the bins, weighted by their counts. It is
+    /// maintained as values are added and merged rather than recomputed from the bins, so it
+    /// ignores later collapsing of the bins, and it does not reflect changes made to the stores
+    /// directly.
+    pub fn get_sum(&self) -> Option<f64> {
+        if self.get_count() <= 0.0 {
+            return None;
+        }
+        Some(self.sum)
+    }
+
+    /// Returns the sum of the representative values of the bins like `get_sum`, but recomputed
+    /// from the bins with compensated summation, which keeps its error independent of the number
+    /// of bins and of the order of magnitude of their values.
+    pub fn get_sum_kahan(&self) -> Option<f64> {
+        if self.get_count() <= 0.0 {
+            return None;
+        }
+        // Neumaier's variant, which also compensates terms larger than the running sum.
+        let mut sum: f64 = 0.0;
+        let mut compensation = 0.0;
+        let negative_terms = self
+            .negative_value_store
+            .get_ascending_iter()
+            .map(|bin| -self.index_mapping.value(bin.index) * bin.count);
+        let positive_terms = self
+            .positive_value_store
+            .get_ascending_iter()
+            .map(|bin| self.index_mapping.value(bin.index) * bin.count);
+        for term in negative_terms.chain(positive_terms) {
+            let next = sum + term;
+            if sum.abs() >= term.abs() {
+                compensation += (sum - next) + term;
+            } else {
+                compensation += (term - next) + sum;
+            }
+            sum = next;
+        }
+        Some(sum + compensation)
+    }
 
-        Some(sum)
+    fn bins_sum(&self) -> f64 {
+        self.positive_value_store.get_sum(&self.index_mapping)
+            - self.negative_value_store.get_sum(&self.index_mapping)
     }
 
-    pub fn get_max(&mut self) -> Option<f64> {
+    pub fn get_max(&self) -> Option<f64> {
         if !self.positive_value_store.is_empty() {
             Some(
                 self.index_mapping
@@ -105,7 +573,7 @@ impl DDSketch {
         }
     }
 
-    pub fn get_min(&mut self) -> Option<f64> {
+    pub fn get_min(&self) -> Option<f64> {
         if !self.negative_value_store.is_empty() {
             Some(
                 -self
@@ -124,7 +592,35 @@ impl DDSketch {
         }
     }
 
-    pub fn get_average(&mut self) -> Option<f64> {
+    /// Returns the number of non-empty bins across both stores, not counting the zero bucket.
+    pub fn num_bins(&self) -> usize {
+        self.negative_value_store.get_ascending_iter().count()
+            + self.positive_value_store.get_ascending_iter().count()
+    }
+
+    /// Returns the lowest and highest indexes of the bins of both stores. Negative values are
+    /// indexed by their absolute value.
+    pub fn index_range(&self) -> Option<(i32, i32)> {
+        let negative_range = store_index_range(self.negative_value_store.as_ref());
+        let positive_range = store_index_range(self.positive_value_store.as_ref());
+        let range = (
+            negative_range.0.min(positive_range.0),
+            negative_range.1.max(positive_range.1),
+        );
+        if range.0 <= range.1 {
+            Some(range)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the representative values of the lowest and highest non-empty bins, including the
+    /// zero bucket.
+    pub fn value_range(&self) -> Option<(f64, f64)> {
+        Some((self.get_min()?, self.get_max()?))
+    }
+
+    pub fn get_average(&self) -> Option<f64> {
         let count = self.get_count();
         if count <= 0.0 {
             return None;
@@ -132,7 +628,184 @@ impl DDSketch {
         Some(self.get_sum()? / count)
     }
 
-    pub fn get_value_at_quantile(self: &mut DDSketch, quantile: f64) -> Option<f64> {
+    pub fn get_variance(&self) -> Option<f64> {
+        let count = self.get_count();
+        if count <= 0.0 {
+            return None;
+        }
+
+        let mut sum = 0.0;
+        let mut sum_of_squares = 0.0;
+        for bin in self.negative_value_store.get_ascending_iter() {
+            let value = -self.index_mapping.value(bin.index);
+            sum += value * bin.count;
+            sum_of_squares += value * value * bin.count;
+        }
+        for bin in self.positive_value_store.get_ascending_iter() {
+            let value = self.index_mapping.value(bin.index);
+            sum += value * bin.count;
+            sum_of_squares += value * value * bin.count;
+        }
+
+        let mean = sum / count;
+        Some(f64::max(0.0, sum_of_squares / count - mean * mean))
+    }
+
+    pub fn get_std_dev(&self) -> Option<f64> {
+        Some(self.get_variance()?.sqrt())
+    }
+
+    pub fn summary(&self, quantiles: &[f64]) -> Summary {
+        let count = self.get_count();
+        let sum = self.get_sum();
+        Summary {
+            count,
+            sum,
+            min: self.get_min(),
+            max: self.get_max(),
+            mean: sum.map(|sum| sum / count),
+            quantiles: self.values_at_quantiles(quantiles),
+        }
+    }
+
+    /// Returns `(q, value)` pairs for `q` from 0 to 1 in increments of `step`, computed in a single
+    /// traversal of the bins. The table is empty if the sketch is.
+    pub fn percentile_table(&self, step: f64) -> Result<Vec<(f64, f64)>, Error> {
+        if !(step > 0.0 && step <= 1.0) {
+            return Err(Error::InvalidArgument("The step must be in (0, 1]."));
+        }
+        // Tolerates the rounding of steps such as 0.1 so that the table ends at 1.
+        let num_steps = (1.0 / step + 1e-9).floor() as usize;
+        let quantiles: Vec<f64> = (0..=num_steps)
+            .map(|i| f64::min(1.0, i as f64 * step))
+            .collect();
+        Ok(quantiles
+            .iter()
+            .zip(self.values_at_quantiles(&quantiles))
+            .filter_map(|(q, value)| Some((*q, value?)))
+            .collect())
+    }
+
+    /// Returns the quantile interpolated linearly between the representative values of the two
+    /// bins whose middle ranks straddle the target rank. This reduces the quantization jitter of
+    /// `get_value_at_quantile` at the cost of a slight bias.
+    pub fn get_quantile_interpolated(&self, q: f64) -> Option<f64> {
+        if !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+
+        let count = self.get_count();
+        if count <= 0.0 {
+            return None;
+        }
+
+        let rank = q * (count - 1.0);
+        let negative_bins = self
+            .negative_value_store
+            .get_descending_iter()
+            .map(|bin| (-self.index_mapping.value(bin.index), bin.count));
+        let positive_bins = self
+            .positive_value_store
+            .get_ascending_iter()
+            .map(|bin| (self.index_mapping.value(bin.index), bin.count));
+
+        let mut n: f64 = 0.0;
+        let mut previous: Option<(f64, f64)> = None;
+        for (value, bin_count) in negative_bins
+            .chain(std::iter::once((0.0, self.zero_count)))
+            .chain(positive_bins)
+        {
+            if bin_count <= 0.0 {
+                continue;
+            }
+            let middle_rank = n + (bin_count - 1.0) / 2.0;
+            n += bin_count;
+            if middle_rank >= rank {
+                return match previous {
+                    Some((previous_value, previous_middle_rank)) => Some(
+                        previous_value
+                            + (value - previous_value) * (rank - previous_middle_rank)
+                                / (middle_rank - previous_middle_rank),
+                    ),
+                    None => Some(value),
+                };
+            }
+            previous = Some((value, middle_rank));
+        }
+
+        previous.map(|previous| previous.0)
+    }
+
+    /// Returns, for each quantile, the relative error of the sketch estimate against the exact
+    /// quantile of `sorted_values`, or NaN if either is undefined.
+    pub fn verify_accuracy(&self, sorted_values: &[f64], quantiles: &[f64]) -> Vec<f64> {
+        quantiles
+            .iter()
+            .map(|quantile| {
+                if sorted_values.is_empty() || !(0.0..=1.0).contains(quantile) {
+                    return f64::NAN;
+                }
+                let rank = (quantile * (sorted_values.len() - 1) as f64).floor() as usize;
+                let expected = sorted_values[rank];
+                match self.get_value_at_quantile(*quantile) {
+                    Some(value) if expected == 0.0 => value.abs(),
+                    Some(value) => ((value - expected) / expected).abs(),
+                    None => f64::NAN,
+                }
+            })
+            .collect()
+    }
+
+    pub fn qq_against(&self, reference: &DDSketch, quantiles: &[f64]) -> Vec<(f64, f64)> {
+        quantiles
+            .iter()
+            .filter_map(|quantile| {
+                Some((
+                    reference.get_value_at_quantile(*quantile)?,
+                    self.get_value_at_quantile(*quantile)?,
+                ))
+            })
+            .collect()
+    }
+
+    // Walks the bins once for all the quantiles, in increasing order of rank.
+    fn values_at_quantiles(&self, quantiles: &[f64]) -> Vec<Option<f64>> {
+        let mut values = vec![None; quantiles.len()];
+        let count = self.get_count();
+        if count <= 0.0 {
+            return values;
+        }
+
+        let mut order: Vec<usize> = (0..quantiles.len())
+            .filter(|i| (0.0..=1.0).contains(&quantiles[*i]))
+            .collect();
+        order.sort_by(|a, b| quantiles[*a].total_cmp(&quantiles[*b]));
+        let mut order = order.into_iter().peekable();
+
+        let negative_bins = self
+            .negative_value_store
+            .get_descending_iter()
+            .map(|bin| (-self.index_mapping.value(bin.index), bin.count));
+        let zero_bin = (0.0, self.zero_count);
+        let positive_bins = self
+            .positive_value_store
+            .get_ascending_iter()
+            .map(|bin| (self.index_mapping.value(bin.index), bin.count));
+
+        let mut n: f64 = 0.0;
+        for (value, bin_count) in negative_bins
+            .chain(std::iter::once(zero_bin))
+            .chain(positive_bins)
+        {
+            n += bin_count;
+            while let Some(i) = order.next_if(|i| n > quantiles[*i] * (count - 1.0)) {
+                values[i] = Some(value);
+            }
+        }
+        values
+    }
+
+    pub fn get_value_at_quantile(&self, quantile: f64) -> Option<f64> {
         if !(0.0..=1.0).contains(&quantile) {
             return None;
         }
@@ -142,159 +815,1400 @@ impl DDSketch {
             return None;
         }
 
-        let rank = quantile * (count - 1.0);
+        // With a total count below 1, every quantile falls into the first bin.
+        self.get_value_at_rank(f64::max(0.0, quantile * (count - 1.0)))
+    }
+
+    /// Returns the value at the quantile `1 - upper` without forming it, by accumulating the bins
+    /// from the top, which keeps extreme upper quantiles such as `1e-17` from rounding to `1.0`.
+    pub fn get_value_at_upper_quantile(&self, upper: f64) -> Option<f64> {
+        if !(0.0..=1.0).contains(&upper) {
+            return None;
+        }
+
+        let count = self.get_count();
+        if count <= 0.0 {
+            return None;
+        }
+
+        // Mirrors the ascending rank of the quantile: `1 - upper` is at `count - 1 - upper_rank`.
+        let upper_rank = upper * (count - 1.0) + 1.0;
+
+        let mut n: f64 = 0.0;
+        for bin in self.positive_value_store.get_descending_iter() {
+            n += bin.count;
+            if n >= upper_rank {
+                return Some(self.index_mapping.value(bin.index));
+            }
+        }
+
+        n += self.zero_count;
+        if n >= upper_rank {
+            return Some(0.0);
+        }
+
+        for bin in self.negative_value_store.get_ascending_iter() {
+            n += bin.count;
+            if n >= upper_rank {
+                return Some(-self.index_mapping.value(bin.index));
+            }
+        }
+
+        self.get_min()
+    }
+
+    /// Returns the value at the absolute `rank`, in `[0, count)`, e.g. `0` for the minimum and
+    /// `count - 1` for the maximum.
+    pub fn get_value_at_rank(&self, rank: f64) -> Option<f64> {
+        if !(0.0..self.get_count()).contains(&rank) {
+            return None;
+        }
 
         let mut n: f64 = 0.0;
 
         let negative_bin_iterator = self.negative_value_store.get_descending_iter();
         for bin in negative_bin_iterator {
-            n += bin.1;
+            debug_assert!(bin.count >= 0.0);
+            n += bin.count;
+            if n > rank {
+                return Some(-self.index_mapping.value(bin.index));
+            }
+        }
+
+        n += self.zero_count;
+        if n > rank {
+            return Some(0.0);
+        }
+
+        let positive_bin_iterator = self.positive_value_store.get_ascending_iter();
+        for bin in positive_bin_iterator {
+            debug_assert!(bin.count >= 0.0);
+            n += bin.count;
+            if n > rank {
+                return Some(self.index_mapping.value(bin.index));
+            }
+        }
+
+        None
+    }
+
+    pub fn quantile_upper_estimate(&self, q: f64) -> Option<f64> {
+        self.get_quantile_bounds(q).map(|bounds| bounds.1)
+    }
+
+    pub fn quantile_lower_estimate(&self, q: f64) -> Option<f64> {
+        self.get_quantile_bounds(q).map(|bounds| bounds.0)
+    }
+
+    /// Returns the range of the values that may have been added to the bin holding the quantile,
+    /// i.e. around the value returned by `get_value_at_quantile` within the relative accuracy.
+    pub fn get_quantile_bounds(&self, quantile: f64) -> Option<(f64, f64)> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return None;
+        }
+
+        let count = self.get_count();
+        if count <= 0.0 {
+            return None;
+        }
+
+        let rank = quantile * (count - 1.0);
+
+        let mut n: f64 = 0.0;
+
+        for bin in self.negative_value_store.get_descending_iter() {
+            n += bin.count;
+            if n > rank {
+                let (lower, upper) = self.bin_bounds(self.negative_value_store.as_ref(), bin.index);
+                return Some((-upper, -lower));
+            }
+        }
+
+        n += self.zero_count;
+        if n > rank {
+            return Some((-self.min_indexed_value, self.min_indexed_value));
+        }
+
+        for bin in self.positive_value_store.get_ascending_iter() {
+            n += bin.count;
+            if n > rank {
+                return Some(self.bin_bounds(self.positive_value_store.as_ref(), bin.index));
+            }
+        }
+
+        None
+    }
+
+    // Bounds of the absolute values that may have been added to the bin, widened up to the
+    // indexable range when the bin holds counts collapsed from other bins.
+    fn bin_bounds(&self, store: &dyn Store, index: i32) -> (f64, f64) {
+        let value = self.index_mapping.value(index);
+        let error = 1.0 + self.index_mapping.relative_accuracy();
+        let mut lower = value / error;
+        let mut upper = value * error;
+        if store.collapsed_low_index() == Some(index) {
+            lower = self.min_indexed_value;
+        }
+        if store.collapsed_high_index() == Some(index) {
+            upper = self.max_indexed_value;
+        }
+        (lower, upper)
+    }
+
+    /// Returns the `(value, count)` of every bin in increasing order of value, including the empty
+    /// bins within the index range of each store, and the zero bucket in between.
+    pub fn dense_bins(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        let negative_bins = self
+            .negative_value_store
+            .get_ascending_iter_dense()
+            .rev()
+            .map(|bin| (-self.index_mapping.value(bin.index), bin.count));
+        let positive_bins = self
+            .positive_value_store
+            .get_ascending_iter_dense()
+            .map(|bin| (self.index_mapping.value(bin.index), bin.count));
+        negative_bins
+            .chain(std::iter::once((0.0, self.zero_count)))
+            .chain(positive_bins)
+    }
+
+    pub fn smoothed_mode_count(&self, bandwidth_bins: usize) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+
+        let mut counts = Vec::new();
+        let negative_value_store = &self.negative_value_store;
+        if !negative_value_store.is_empty() {
+            let offset = negative_value_store.get_offset();
+            for index in (negative_value_store.get_min_index()
+                ..negative_value_store.get_max_index() + 1)
+                .rev()
+            {
+                counts.push(negative_value_store.get_count(index - offset));
+            }
+        }
+        counts.push(self.zero_count);
+        let positive_value_store = &self.positive_value_store;
+        if !positive_value_store.is_empty() {
+            let offset = positive_value_store.get_offset();
+            for index in
+                positive_value_store.get_min_index()..positive_value_store.get_max_index() + 1
+            {
+                counts.push(positive_value_store.get_count(index - offset));
+            }
+        }
+
+        let mut prefix_sums = Vec::with_capacity(counts.len() + 1);
+        prefix_sums.push(0.0);
+        for count in counts.iter() {
+            prefix_sums.push(prefix_sums[prefix_sums.len() - 1] + count);
+        }
+
+        let bandwidth_bins = bandwidth_bins.max(1);
+        let mut mode_count = 0;
+        let mut previous = 0.0;
+        let mut rising = false;
+        for i in 0..counts.len() + 1 {
+            let smoothed = if i < counts.len() {
+                let from = i.saturating_sub((bandwidth_bins - 1) / 2);
+                let to = usize::min(counts.len(), i + bandwidth_bins / 2 + 1);
+                (prefix_sums[to] - prefix_sums[from]) / (to - from) as f64
+            } else {
+                0.0
+            };
+            if smoothed > previous {
+                rising = true;
+            } else if smoothed < previous && rising {
+                mode_count += 1;
+                rising = false;
+            }
+            previous = smoothed;
+        }
+        mode_count
+    }
+
+    pub fn weighted_value_at_quantile(
+        sketches: &[(&DDSketch, f64)],
+        quantile: f64,
+    ) -> Result<Option<f64>, Error> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Ok(None);
+        }
+
+        let index_mapping = match sketches.first() {
+            Some((sketch, _weight)) => &sketch.index_mapping,
+            None => return Ok(None),
+        };
+
+        let mut negative_bins: BTreeMap<i32, f64> = BTreeMap::new();
+        let mut positive_bins: BTreeMap<i32, f64> = BTreeMap::new();
+        let mut zero_count = 0.0;
+        for (sketch, weight) in sketches {
+            if sketch.index_mapping != *index_mapping {
+                return Err(Error::InvalidArgument("Unmatched indexMapping."));
+            }
+            if !weight.is_finite() || *weight < 0.0 {
+                return Err(Error::InvalidArgument(
+                    "The weight must be finite and non-negative.",
+                ));
+            }
+            for bin in sketch.negative_value_store.get_ascending_iter() {
+                *negative_bins.entry(bin.index).or_insert(0.0) += bin.count * weight;
+            }
+            for bin in sketch.positive_value_store.get_ascending_iter() {
+                *positive_bins.entry(bin.index).or_insert(0.0) += bin.count * weight;
+            }
+            zero_count += sketch.zero_count * weight;
+        }
+
+        let count =
+            zero_count + negative_bins.values().sum::<f64>() + positive_bins.values().sum::<f64>();
+        if count <= 0.0 {
+            return Ok(None);
+        }
+
+        let rank = quantile * (count - 1.0);
+
+        let mut n: f64 = 0.0;
+
+        for (index, bin_count) in negative_bins.iter().rev() {
+            n += bin_count;
             if n > rank {
-                return Some(-self.index_mapping.value(bin.0));
+                return Ok(Some(-index_mapping.value(*index)));
+            }
+        }
+
+        n += zero_count;
+        if n > rank {
+            return Ok(Some(0.0));
+        }
+
+        for (index, bin_count) in positive_bins.iter() {
+            n += bin_count;
+            if n > rank {
+                return Ok(Some(index_mapping.value(*index)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn decode_and_merge_with(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let count = self.get_count();
+        let mut exact_min = f64::INFINITY;
+        let mut exact_max = f64::NEG_INFINITY;
+        let mut input = Input::wrap(bytes);
+        while input.has_remaining() {
+            let flag = Flag::decode(&mut input)?;
+            let flag_type = flag.get_type()?;
+            match flag_type {
+                FlagType::PositiveStore => {
+                    let mode = BinEncodingMode::of_flag(flag.get_marker())?;
+                    self.positive_value_store
+                        .decode_and_merge_with(&mut input, mode)?;
+                }
+                FlagType::NegativeStore => {
+                    let mode = BinEncodingMode::of_flag(flag.get_marker())?;
+                    self.negative_value_store
+                        .decode_and_merge_with(&mut input, mode)?;
+                }
+                FlagType::IndexMapping => {
+                    let layout = IndexMappingLayout::of_flag(&flag)?;
+                    let gamma = input.read_double_le()?;
+                    let index_offset = input.read_double_le()?;
+                    let decoded_index_mapping =
+                        IndexMapping::with_gamma_offset(layout, gamma, index_offset)?;
+                    if self.index_mapping != decoded_index_mapping {
+                        return Err(Error::InvalidArgument("Unmatched IndexMapping"));
+                    }
+                }
+                FlagType::SketchFeatures => {
+                    if Flag::ZERO_COUNT == flag {
+                        self.zero_count += serde::decode_var_double(&mut input)?;
+                    } else if Flag::MIN == flag {
+                        exact_min = f64::min(exact_min, input.read_double_le()?);
+                    } else if Flag::MAX == flag {
+                        exact_max = f64::max(exact_max, input.read_double_le()?);
+                    } else {
+                        serde::ignore_exact_summary_statistic_flags(&mut input, flag)?;
+                    }
+                }
+            }
+        }
+        self.merge_exact_extremes(exact_min, exact_max, self.get_count() - count);
+        self.sum = self.bins_sum();
+        Ok(())
+    }
+
+    /// Merges the encoded sketch like `decode_and_merge_with`, but also accepts bytes encoded with
+    /// a finer index mapping, whose bins are re-binned into this sketch's mapping on the fly by
+    /// their representative values.
+    pub fn decode_and_merge_downsampled(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let count = self.get_count();
+        let mut exact_min = f64::INFINITY;
+        let mut exact_max = f64::NEG_INFINITY;
+        let mut input = Input::wrap(bytes);
+        let mut decoded_index_mapping = self.index_mapping.clone();
+        while input.has_remaining() {
+            let flag = Flag::decode(&mut input)?;
+            let flag_type = flag.get_type()?;
+            match flag_type {
+                FlagType::PositiveStore | FlagType::NegativeStore => {
+                    let mode = BinEncodingMode::of_flag(flag.get_marker())?;
+                    let store = if let FlagType::PositiveStore = flag_type {
+                        &mut self.positive_value_store
+                    } else {
+                        &mut self.negative_value_store
+                    };
+                    if decoded_index_mapping == self.index_mapping {
+                        store.decode_and_merge_with(&mut input, mode)?;
+                    } else {
+                        let index_mapping = &self.index_mapping;
+                        let min_indexed_value = self.min_indexed_value;
+                        let zero_count = &mut self.zero_count;
+                        mode.decode_bins(&mut input, |index, count| {
+                            let value = decoded_index_mapping.value(index);
+                            if value >= min_indexed_value {
+                                store.add(index_mapping.index(value), count);
+                            } else {
+                                *zero_count += count;
+                            }
+                        })?;
+                    }
+                }
+                FlagType::IndexMapping => {
+                    let layout = IndexMappingLayout::of_flag(&flag)?;
+                    let gamma = input.read_double_le()?;
+                    let index_offset = input.read_double_le()?;
+                    decoded_index_mapping =
+                        IndexMapping::with_gamma_offset(layout, gamma, index_offset)?;
+                    if decoded_index_mapping.gamma() > self.index_mapping.gamma() {
+                        return Err(Error::InvalidArgument(
+                            "The encoded index mapping must not be coarser than the sketch's.",
+                        ));
+                    }
+                }
+                FlagType::SketchFeatures => {
+                    if Flag::ZERO_COUNT == flag {
+                        self.zero_count += serde::decode_var_double(&mut input)?;
+                    } else if Flag::MIN == flag {
+                        exact_min = f64::min(exact_min, input.read_double_le()?);
+                    } else if Flag::MAX == flag {
+                        exact_max = f64::max(exact_max, input.read_double_le()?);
+                    } else {
+                        serde::ignore_exact_summary_statistic_flags(&mut input, flag)?;
+                    }
+                }
+            }
+        }
+        self.merge_exact_extremes(exact_min, exact_max, self.get_count() - count);
+        self.sum = self.bins_sum();
+        Ok(())
+    }
+
+    /// Merges `other` into this sketch, keeping the store types of this sketch whatever those of
+    /// `other` are: collapsing stores keep their maximum number of bins by collapsing the merged
+    /// bins that do not fit, while unbounded stores grow to hold all of them.
+    pub fn merge_with(&mut self, other: &DDSketch) -> Result<(), Error> {
+        if self.index_mapping != other.index_mapping {
+            return Err(Error::InvalidArgument("Unmatched indexMapping."));
+        }
+        merge_store(
+            self.negative_value_store.as_mut(),
+            other.negative_value_store.as_ref(),
+        );
+        merge_store(
+            self.positive_value_store.as_mut(),
+            other.positive_value_store.as_ref(),
+        );
+        self.zero_count += other.zero_count;
+        self.sum += other.sum;
+        self.merge_exact_extremes_of(other);
+        Ok(())
+    }
+
+    /// Merges `other` like `merge_with`, then scales all the counts down uniformly if needed so
+    /// that the total count does not exceed `target_count`, which ages the older data out over
+    /// repeated merges. The quantiles are not affected by the scaling, except that integer counts
+    /// are rounded, carrying the rounding errors over from bin to bin.
+    pub fn merge_with_cap(&mut self, other: &DDSketch, target_count: f64) -> Result<(), Error> {
+        if !(target_count.is_finite() && target_count > 0.0) {
+            return Err(Error::InvalidArgument(
+                "The target count must be finite and positive.",
+            ));
+        }
+        self.merge_with(other)?;
+        let count = self.get_count();
+        if count > target_count {
+            let factor = target_count / count;
+            scale_store(self.negative_value_store.as_mut(), factor);
+            scale_store(self.positive_value_store.as_mut(), factor);
+            self.zero_count *= factor;
+            self.sum *= factor;
+        }
+        Ok(())
+    }
+
+    /// Merges `other` like `merge_with`, and reports how much of the count of both sketches was
+    /// folded into the collapsed edge bins of the stores in the process.
+    pub fn merge_with_report(&mut self, other: &DDSketch) -> Result<MergeReport, Error> {
+        if self.index_mapping != other.index_mapping {
+            return Err(Error::InvalidArgument("Unmatched indexMapping."));
+        }
+        let negative_bins: Vec<Bin> = self.negative_value_store.get_ascending_iter().collect();
+        let positive_bins: Vec<Bin> = self.positive_value_store.get_ascending_iter().collect();
+        let count = self.get_count() + other.get_count();
+
+        self.merge_with(other)?;
+
+        let collapsed_count = collapsed_count(
+            self.negative_value_store.as_ref(),
+            negative_bins
+                .into_iter()
+                .chain(other.negative_value_store.get_ascending_iter()),
+        ) + collapsed_count(
+            self.positive_value_store.as_ref(),
+            positive_bins
+                .into_iter()
+                .chain(other.positive_value_store.get_ascending_iter()),
+        );
+        Ok(MergeReport {
+            collapsed_count,
+            dropped_out_of_range: f64::max(0.0, count - self.get_count()),
+        })
+    }
+
+    /// Grows both stores at once to hold the bins of the values between `min_value` and
+    /// `max_value`, so that accepting them does not reallocate. Collapsing stores only reserve up
+    /// to their maximum number of bins.
+    pub fn reserve_value_range(&mut self, min_value: f64, max_value: f64) -> Result<(), Error> {
+        if min_value.is_nan() || max_value.is_nan() || min_value > max_value {
+            return Err(Error::InvalidArgument(
+                "The value range must be ordered and not NaN.",
+            ));
+        }
+        if min_value < -self.max_indexed_value || max_value > self.max_indexed_value {
+            return Err(Error::InvalidArgument(
+                "The value range is out of the range of the index mapping.",
+            ));
+        }
+        if max_value >= self.min_indexed_value {
+            let low = f64::max(min_value, self.min_indexed_value);
+            self.positive_value_store.reserve_index_range(
+                self.index_mapping.index(low),
+                self.index_mapping.index(max_value),
+            );
+        }
+        if min_value <= -self.min_indexed_value {
+            let low = f64::max(-max_value, self.min_indexed_value);
+            self.negative_value_store.reserve_index_range(
+                self.index_mapping.index(low),
+                self.index_mapping.index(-min_value),
+            );
+        }
+        Ok(())
+    }
+
+    /// Merges all the sketches into this one, growing each store once to the union of the index
+    /// ranges beforehand rather than repeatedly while merging.
+    pub fn merge_with_capacity_hint(&mut self, others: &[&DDSketch]) -> Result<(), Error> {
+        if others
+            .iter()
+            .any(|other| self.index_mapping != other.index_mapping)
+        {
+            return Err(Error::InvalidArgument("Unmatched indexMapping."));
+        }
+
+        let mut negative_range = store_index_range(self.negative_value_store.as_ref());
+        let mut positive_range = store_index_range(self.positive_value_store.as_ref());
+        for other in others {
+            let range = store_index_range(other.negative_value_store.as_ref());
+            negative_range = (negative_range.0.min(range.0), negative_range.1.max(range.1));
+            let range = store_index_range(other.positive_value_store.as_ref());
+            positive_range = (positive_range.0.min(range.0), positive_range.1.max(range.1));
+        }
+        self.negative_value_store
+            .reserve_index_range(negative_range.0, negative_range.1);
+        self.positive_value_store
+            .reserve_index_range(positive_range.0, positive_range.1);
+
+        for other in others {
+            self.merge_with(other)?;
+        }
+        Ok(())
+    }
+
+    pub fn merge_all(sketches: &[DDSketch]) -> Result<Option<DDSketch>, Error> {
+        let first = match sketches.first() {
+            Some(first) => first,
+            None => return Ok(None),
+        };
+        if sketches
+            .iter()
+            .any(|sketch| sketch.index_mapping != first.index_mapping)
+        {
+            return Err(Error::InvalidArgument("Unmatched indexMapping."));
+        }
+        let mut merged = first.fresh();
+        for sketch in sketches {
+            merged.merge_with(sketch)?;
+        }
+        Ok(Some(merged))
+    }
+
+    pub fn merge_from(&mut self, other: DDSketch) -> Result<(), Error> {
+        if self.index_mapping != other.index_mapping {
+            return Err(Error::InvalidArgument("Unmatched indexMapping."));
+        }
+        self.merge_exact_extremes_of(&other);
+        other
+            .negative_value_store
+            .drain_into(self.negative_value_store.as_mut());
+        other
+            .positive_value_store
+            .drain_into(self.positive_value_store.as_mut());
+        self.zero_count += other.zero_count;
+        self.sum += other.sum;
+        Ok(())
+    }
+
+    /// Subtracts the bin counts of `other` from those of this sketch, clamping them at zero. This
+    /// is approximate: counts cannot go negative, and a bin of `other` that this sketch does not
+    /// have, e.g. because it was collapsed, is ignored.
+    pub fn subtract(&mut self, other: &DDSketch) -> Result<(), Error> {
+        if self.index_mapping != other.index_mapping {
+            return Err(Error::InvalidArgument("Unmatched indexMapping."));
+        }
+        self.negative_value_store
+            .subtract_with(other.negative_value_store.get_descending_stream());
+        self.positive_value_store
+            .subtract_with(other.positive_value_store.get_descending_stream());
+        self.zero_count = f64::max(0.0, self.zero_count - other.zero_count);
+        self.sum = self.bins_sum();
+        self.forget_exact_extremes();
+        Ok(())
+    }
+
+    /// Returns the earth mover's distance between the distributions of this sketch and `other`,
+    /// both normalized to a total count of 1, with every bin standing for its representative value.
+    pub fn emd_distance(&self, other: &DDSketch) -> Result<f64, Error> {
+        if self.index_mapping != other.index_mapping {
+            return Err(Error::InvalidArgument("Unmatched indexMapping."));
+        }
+        let (count, other_count) = (self.get_count(), other.get_count());
+        if count <= 0.0 || other_count <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "The distance to an empty sketch is undefined.",
+            ));
+        }
+
+        // Integrates the absolute difference of the cumulative distributions between consecutive
+        // values of either sketch.
+        let values = self.ascending_weighted_values();
+        let other_values = other.ascending_weighted_values();
+        let (mut i, mut j) = (0, 0);
+        let (mut cdf, mut other_cdf): (f64, f64) = (0.0, 0.0);
+        let mut previous: Option<f64> = None;
+        let mut distance = 0.0;
+        while i < values.len() || j < other_values.len() {
+            let value = match (values.get(i), other_values.get(j)) {
+                (Some(a), Some(b)) => f64::min(a.0, b.0),
+                (Some(a), None) => a.0,
+                (None, Some(b)) => b.0,
+                (None, None) => unreachable!(),
+            };
+            if let Some(previous) = previous {
+                distance += (cdf - other_cdf).abs() * (value - previous);
+            }
+            while i < values.len() && values[i].0 == value {
+                cdf += values[i].1 / count;
+                i += 1;
+            }
+            while j < other_values.len() && other_values[j].0 == value {
+                other_cdf += other_values[j].1 / other_count;
+                j += 1;
+            }
+            previous = Some(value);
+        }
+        Ok(distance)
+    }
+
+    // The representative values of the non-empty bins and of the zero bucket in ascending order,
+    // with their counts.
+    fn ascending_weighted_values(&self) -> Vec<(f64, f64)> {
+        let mut values: Vec<(f64, f64)> = self
+            .negative_value_store
+            .get_descending_iter()
+            .map(|bin| (-self.index_mapping.value(bin.index), bin.count))
+            .collect();
+        if self.zero_count > 0.0 {
+            values.push((0.0, self.zero_count));
+        }
+        values.extend(
+            self.positive_value_store
+                .get_ascending_iter()
+                .map(|bin| (self.index_mapping.value(bin.index), bin.count)),
+        );
+        values
+    }
+
+    /// Encodes the sketch in the format of the reference implementations. The bins are written
+    /// by index regardless of how the stores lay them out, so sketches that accepted the same
+    /// values in any order encode to the same bytes.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut output = Output::with_capacity(64);
+        self.encode_to(&mut output)?;
+        Ok(output.trim())
+    }
+
+    /// Encodes the sketch like `encode`, appending the bytes to `output`. Nothing delimits the
+    /// sketch in `output`, callers batching several sketches write their own length prefixes.
+    pub fn encode_to(&self, output: &mut Output) -> Result<(), Error> {
+        self.encode_bins(output, None)
+    }
+
+    /// Encodes the sketch like `encode`, but with the stores encoding their bins with `mode`
+    /// rather than with the most compact mode, unless `mode` cannot represent them.
+    pub fn encode_with_mode(&self, mode: BinEncodingMode) -> Result<Vec<u8>, Error> {
+        let mut output = Output::with_capacity(64);
+        self.encode_bins(&mut output, Some(mode))?;
+        Ok(output.trim())
+    }
+
+    fn encode_bins(&self, output: &mut Output, mode: Option<BinEncodingMode>) -> Result<(), Error> {
+        self.index_mapping.encode(output)?;
+
+        if self.zero_count != 0.0 {
+            Flag::ZERO_COUNT.encode(output)?;
+            serde::encode_var_double(output, self.zero_count)?;
+        }
+
+        if let (Some(min), Some(max)) = (self.get_exact_min(), self.get_exact_max()) {
+            Flag::MIN.encode(output)?;
+            output.write_double_le(min)?;
+            Flag::MAX.encode(output)?;
+            output.write_double_le(max)?;
+        }
+
+        for (store, store_flag_type) in [
+            (&self.positive_value_store, FlagType::PositiveStore),
+            (&self.negative_value_store, FlagType::NegativeStore),
+        ] {
+            match mode {
+                Some(mode) => store.encode_with_mode(output, store_flag_type, mode)?,
+                None => store.encode(output, store_flag_type)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encodes the sketch like `encode`. Encoding into memory cannot fail, which makes this
+    /// convenient across FFI boundaries such as wasm-bindgen.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode().expect("encoding into memory cannot fail")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<DDSketch, Error> {
+        DDSketch::decode(bytes)
+    }
+
+    /// Encodes the sketch like `encode`, prefixed with a magic byte and a format version so that
+    /// future format changes can be detected by `decode_versioned`. Use `encode` for interop with
+    /// the reference implementations.
+    pub fn encode_versioned(&self) -> Vec<u8> {
+        let mut bytes = vec![VERSIONED_MAGIC, FORMAT_VERSION];
+        bytes.extend(self.to_bytes());
+        bytes
+    }
+
+    #[cfg(feature = "std")]
+    pub fn encode_to_writer<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        w.write_all(&self.encode()?)?;
+        Ok(())
+    }
+
+    /// Encodes the sketch like `encode`, then compresses it as a gzip stream. Compressed blobs
+    /// always start with the gzip magic bytes `0x1f 0x8b`, which a native encoding never starts
+    /// with since it leads with an index mapping flag.
+    #[cfg(feature = "flate2")]
+    pub fn encode_compressed(&self) -> Result<Vec<u8>, Error> {
+        let bytes = self.encode()?;
+        let mut encoder =
+            flate2::write::GzEncoder::new(Vec::with_capacity(64), flate2::Compression::default());
+        encoder.write_all(&bytes)?;
+        Ok(encoder.finish()?)
+    }
+
+    /// Returns the approximate memory footprint of the sketch in bytes, including the heap
+    /// allocations of both stores.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.negative_value_store.memory_usage()
+            + self.positive_value_store.memory_usage()
+    }
+
+    /// Returns an upper bound of the size in bytes of the output of `encode`.
+    pub fn max_serialized_size(&self) -> usize {
+        // Index mapping flag and its two doubles, the zero count flag and its var double, then the
+        // min and max flags and their doubles.
+        let mut size = 1 + 8 + 8 + 1 + 9 + 2 * (1 + 8);
+        for store in [&self.negative_value_store, &self.positive_value_store] {
+            if !store.is_empty() {
+                let num_bins =
+                    (store.get_max_index() as i64 - store.get_min_index() as i64 + 1) as usize;
+                // Flag, bin count, start index and index delta, then one var double per bin.
+                size += 1 + 9 + 9 + 1 + 9 * num_bins;
+            }
+        }
+        size
+    }
+
+    pub fn encode_checked(&self, max_bytes: usize) -> Result<Vec<u8>, Error> {
+        for store in [&self.negative_value_store, &self.positive_value_store] {
+            if !store.is_empty()
+                && store
+                    .get_max_index()
+                    .checked_sub(store.get_min_index())
+                    .is_none()
+            {
+                return Err(Error::InvalidArgument(
+                    "The index range is too large to be encoded.",
+                ));
             }
         }
 
-        n += self.zero_count;
-        if n > rank {
-            return Some(0.0);
+        let bytes = self.encode()?;
+        if bytes.len() > max_bytes {
+            return Err(Error::InvalidArgument(
+                "The encoded sketch exceeds the size limit.",
+            ));
         }
+        Ok(bytes)
+    }
 
-        let positive_bin_iterator = self.positive_value_store.get_ascending_iter();
-        for bin in positive_bin_iterator {
-            n += bin.1;
-            if n > rank {
-                return Some(self.index_mapping.value(bin.0));
+    /// Decodes a sketch, failing on any flag that is not recognized. The recognized sketch
+    /// feature flags are ZERO_COUNT, whose count is added to the zero bucket, and the exact
+    /// summary statistics COUNT, SUM, MIN and MAX, which are skipped.
+    pub fn decode(bytes: &[u8]) -> Result<DDSketch, Error> {
+        DDSketch::decode_with_mode(bytes, false, None)
+    }
+
+    /// Decodes a sketch like `decode`, which is strict by default. It is the counterpart of
+    /// `decode_lenient`.
+    pub fn decode_strict(bytes: &[u8]) -> Result<DDSketch, Error> {
+        DDSketch::decode(bytes)
+    }
+
+    /// Decodes a sketch like `decode`, but skips the unrecognized sketch feature flags,
+    /// assuming that their payload is prefixed by its length in bytes as an unsigned var-long.
+    pub fn decode_lenient(bytes: &[u8]) -> Result<DDSketch, Error> {
+        DDSketch::decode_with_mode(bytes, true, None)
+    }
+
+    pub fn decode_versioned(bytes: &[u8]) -> Result<DDSketch, Error> {
+        match bytes {
+            [VERSIONED_MAGIC, FORMAT_VERSION, payload @ ..] => DDSketch::decode(payload),
+            [VERSIONED_MAGIC, _, ..] => {
+                Err(Error::InvalidArgument("Unsupported sketch format version."))
             }
+            _ => Err(Error::InvalidArgument("Not a versioned sketch.")),
         }
+    }
 
-        None
+    /// Decodes a sketch from untrusted bytes, failing before decoding if there are more than
+    /// `limits.max_bytes` of them, and before allocating if the bins of a store would span more
+    /// than `limits.max_bins` indexes.
+    pub fn decode_limited(bytes: &[u8], limits: DecodeLimits) -> Result<DDSketch, Error> {
+        if bytes.len() > limits.max_bytes {
+            return Err(Error::InvalidArgument(
+                "The encoded sketch exceeds the byte limit.",
+            ));
+        }
+        DDSketch::decode_with_mode(bytes, false, Some(limits))
     }
 
-    pub fn decode_and_merge_with(&mut self, bytes: &Vec<u8>) -> Result<(), Error> {
+    fn decode_with_mode(
+        bytes: &[u8],
+        lenient: bool,
+        limits: Option<DecodeLimits>,
+    ) -> Result<DDSketch, Error> {
         let mut input = Input::wrap(bytes);
+        let mut positive_value_store = UnboundedSizeDenseStore::new();
+        let mut negative_value_store = UnboundedSizeDenseStore::new();
+        let mut index_mapping = None;
+        let mut zero_count = 0.0;
+        let mut exact_min = f64::INFINITY;
+        let mut exact_max = f64::NEG_INFINITY;
         while input.has_remaining() {
             let flag = Flag::decode(&mut input)?;
             let flag_type = flag.get_type()?;
             match flag_type {
                 FlagType::PositiveStore => {
                     let mode = BinEncodingMode::of_flag(flag.get_marker())?;
-                    self.positive_value_store
-                        .decode_and_merge_with(&mut input, mode)?;
+                    decode_store_bins(&mut positive_value_store, &mut input, mode, limits)?;
                 }
                 FlagType::NegativeStore => {
                     let mode = BinEncodingMode::of_flag(flag.get_marker())?;
-                    self.negative_value_store
-                        .decode_and_merge_with(&mut input, mode)?;
+                    decode_store_bins(&mut negative_value_store, &mut input, mode, limits)?;
                 }
                 FlagType::IndexMapping => {
                     let layout = IndexMappingLayout::of_flag(&flag)?;
                     let gamma = input.read_double_le()?;
                     let index_offset = input.read_double_le()?;
-                    let decoded_index_mapping =
-                        IndexMapping::with_gamma_offset(layout, gamma, index_offset)?;
-                    if self.index_mapping != decoded_index_mapping {
-                        return Err(Error::InvalidArgument("Unmatched IndexMapping"));
-                    }
+                    index_mapping = Some(IndexMapping::with_gamma_offset(
+                        layout,
+                        gamma,
+                        index_offset,
+                    )?);
                 }
                 FlagType::SketchFeatures => {
                     if Flag::ZERO_COUNT == flag {
-                        self.zero_count += serde::decode_var_double(&mut input)?;
+                        zero_count += serde::decode_var_double(&mut input)?;
+                    } else if Flag::MIN == flag {
+                        exact_min = f64::min(exact_min, input.read_double_le()?);
+                    } else if Flag::MAX == flag {
+                        exact_max = f64::max(exact_max, input.read_double_le()?);
+                    } else if lenient && !flag.is_exact_summary_statistic() {
+                        let length = serde::decode_unsigned_var_long(&mut input)?;
+                        input.skip(usize::try_from(length).map_err(|_| {
+                            Error::InvalidArgument("Invalid flag payload length.")
+                        })?)?;
                     } else {
                         serde::ignore_exact_summary_statistic_flags(&mut input, flag)?;
                     }
                 }
             }
         }
-        Ok(())
-    }
 
-    pub fn merge_with(&mut self, other: &DDSketch) -> Result<(), Error> {
-        if self.index_mapping != other.index_mapping {
-            return Err(Error::InvalidArgument("Unmatched indexMapping."));
+        match index_mapping {
+            Some(mapping) => {
+                let mut sketch = DDSketch::new(
+                    mapping,
+                    Box::new(negative_value_store),
+                    Box::new(positive_value_store),
+                );
+                sketch.zero_count = zero_count;
+                sketch.set_exact_extremes(exact_min, exact_max);
+                Ok(sketch)
+            }
+            None => Err(Error::InvalidArgument("No IndexMapping decoded")),
         }
-        self.negative_value_store
-            .merge_with(other.negative_value_store.get_descending_stream());
-        self.positive_value_store
-            .merge_with(other.positive_value_store.get_descending_stream());
-        self.zero_count += other.zero_count;
-        Ok(())
     }
 
-    pub fn encode(&self) -> Result<Vec<u8>, Error> {
-        let mut output = Output::with_capacity(64);
-        self.index_mapping.encode(&mut output)?;
+    #[cfg(feature = "std")]
+    pub fn decode_from_reader<R: Read>(r: &mut R) -> Result<DDSketch, Error> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        DDSketch::decode(&bytes)
+    }
 
-        if self.zero_count != 0.0 {
-            Flag::ZERO_COUNT.encode(&mut output)?;
-            serde::encode_var_double(&mut output, self.zero_count)?;
+    /// Writes the sketches one after the other, each prefixed by its length in bytes as an
+    /// unsigned var-long, as read by `decode_stream`.
+    #[cfg(feature = "std")]
+    pub fn encode_stream<W: Write>(sketches: &[DDSketch], w: &mut W) -> Result<(), Error> {
+        for sketch in sketches {
+            let bytes = sketch.to_bytes();
+            let mut output = Output::with_capacity(9);
+            serde::encode_var_u64(&mut output, bytes.len() as u64)?;
+            w.write_all(&output.trim())?;
+            w.write_all(&bytes)?;
         }
+        Ok(())
+    }
 
-        self.positive_value_store
-            .encode(&mut output, FlagType::PositiveStore)?;
-        self.negative_value_store
-            .encode(&mut output, FlagType::NegativeStore)?;
+    /// Lazily decodes the length-prefixed sketches written by `encode_stream`. The iteration
+    /// stops at the end of the stream or after the first error.
+    #[cfg(feature = "std")]
+    pub fn decode_stream<R: Read>(mut r: R) -> impl Iterator<Item = Result<DDSketch, Error>> {
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let result = match read_length_prefix(&mut r) {
+                Ok(None) => {
+                    done = true;
+                    return None;
+                }
+                Ok(Some(length)) => read_sketch(&mut r, length),
+                Err(error) => Err(error),
+            };
+            done = result.is_err();
+            Some(result)
+        })
+    }
 
-        Ok(output.trim())
+    #[cfg(feature = "flate2")]
+    pub fn decode_compressed(bytes: &[u8]) -> Result<DDSketch, Error> {
+        if !bytes.starts_with(&GZIP_MAGIC) {
+            return Err(Error::InvalidArgument("Not a compressed sketch."));
+        }
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(bytes).read_to_end(&mut decoded)?;
+        DDSketch::decode(&decoded)
     }
 
-    pub fn decode(bytes: &Vec<u8>) -> Result<DDSketch, Error> {
+    /// Reads the exact summary statistics and the zero count of an encoded sketch, skipping the
+    /// bins of both stores.
+    pub fn decode_summary_only(bytes: &[u8]) -> Result<SketchSummary, Error> {
         let mut input = Input::wrap(bytes);
-        let mut positive_value_store = UnboundedSizeDenseStore::new();
-        let mut negative_value_store = UnboundedSizeDenseStore::new();
-        let mut index_mapping = None;
-        let mut zero_count = 0.0;
+        let mut summary = SketchSummary::default();
         while input.has_remaining() {
             let flag = Flag::decode(&mut input)?;
-            let flag_type = flag.get_type()?;
-            match flag_type {
-                FlagType::PositiveStore => {
-                    let mode = BinEncodingMode::of_flag(flag.get_marker())?;
-                    positive_value_store.decode_and_merge_with(&mut input, mode)?;
-                }
-                FlagType::NegativeStore => {
-                    let mode = BinEncodingMode::of_flag(flag.get_marker())?;
-                    negative_value_store.decode_and_merge_with(&mut input, mode)?;
+            match flag.get_type()? {
+                FlagType::PositiveStore | FlagType::NegativeStore => {
+                    BinEncodingMode::of_flag(flag.get_marker())?.skip_bins(&mut input)?;
                 }
                 FlagType::IndexMapping => {
-                    let layout = IndexMappingLayout::of_flag(&flag)?;
-                    let gamma = input.read_double_le()?;
-                    let index_offset = input.read_double_le()?;
-                    index_mapping = Some(IndexMapping::with_gamma_offset(
-                        layout,
-                        gamma,
-                        index_offset,
-                    )?);
+                    IndexMappingLayout::of_flag(&flag)?;
+                    input.read_double_le()?;
+                    input.read_double_le()?;
                 }
                 FlagType::SketchFeatures => {
                     if Flag::ZERO_COUNT == flag {
-                        zero_count += serde::decode_var_double(&mut input)?;
+                        summary.zero_count += serde::decode_var_double(&mut input)?;
+                    } else if Flag::COUNT == flag {
+                        summary.count = Some(serde::decode_var_double(&mut input)?);
+                    } else if Flag::SUM == flag {
+                        summary.sum = Some(input.read_double_le()?);
+                    } else if Flag::MIN == flag {
+                        summary.min = Some(input.read_double_le()?);
+                    } else if Flag::MAX == flag {
+                        summary.max = Some(input.read_double_le()?);
                     } else {
-                        serde::ignore_exact_summary_statistic_flags(&mut input, flag)?;
+                        return Err(Error::InvalidArgument("Unknown Flag."));
                     }
                 }
             }
         }
+        Ok(summary)
+    }
+}
 
-        match index_mapping {
-            Some(mapping) => {
-                let min_indexed_value = f64::max(0.0, mapping.min_indexable_value());
-                let max_indexed_value = mapping.max_indexable_value();
-                Ok(DDSketch {
-                    index_mapping: mapping,
-                    negative_value_store: Box::new(negative_value_store),
-                    positive_value_store: Box::new(positive_value_store),
-                    min_indexed_value,
-                    max_indexed_value,
-                    zero_count,
-                })
+// Reads an unsigned var-long, or None if the reader is already at its end.
+#[cfg(feature = "std")]
+fn read_length_prefix<R: Read>(r: &mut R) -> Result<Option<u64>, Error> {
+    let mut bytes = Vec::with_capacity(9);
+    let mut byte = [0u8];
+    loop {
+        if r.read(&mut byte)? == 0 {
+            if bytes.is_empty() {
+                return Ok(None);
             }
-            None => Err(Error::InvalidArgument("No IndexMapping decoded")),
+            return Err(Error::IoError(std::io::ErrorKind::UnexpectedEof));
+        }
+        bytes.push(byte[0]);
+        if byte[0] < 0x80 || bytes.len() == 9 {
+            break;
+        }
+    }
+    Ok(Some(serde::decode_var_u64(&mut Input::wrap(&bytes))?))
+}
+
+#[cfg(feature = "std")]
+fn read_sketch<R: Read>(r: &mut R, length: u64) -> Result<DDSketch, Error> {
+    let mut bytes = Vec::new();
+    r.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(Error::IoError(std::io::ErrorKind::UnexpectedEof));
+    }
+    DDSketch::decode(&bytes)
+}
+
+// Decodes bins into the store, checking first against the limits, if any, that the store would
+// not span more indexes than allowed. Every bin takes at least one byte, so buffering them is
+// bounded by the input size.
+fn decode_store_bins(
+    store: &mut dyn Store,
+    input: &mut Input,
+    mode: BinEncodingMode,
+    limits: Option<DecodeLimits>,
+) -> Result<(), Error> {
+    let Some(limits) = limits else {
+        return store.decode_and_merge_with(input, mode);
+    };
+    let mut bins = Vec::new();
+    mode.decode_bins(input, |index, count| bins.push((index, count)))?;
+    let (mut min_index, mut max_index) = store_index_range(store);
+    for &(index, _) in &bins {
+        min_index = min_index.min(index);
+        max_index = max_index.max(index);
+    }
+    if !bins.is_empty() && max_index as i64 - min_index as i64 >= limits.max_bins as i64 {
+        return Err(Error::InvalidArgument(
+            "The decoded bins exceed the bin limit.",
+        ));
+    }
+    for (index, count) in bins {
+        store.add(index, count);
+    }
+    Ok(())
+}
+
+fn store_index_range(store: &dyn Store) -> (i32, i32) {
+    if store.is_empty() {
+        (i32::MAX, i32::MIN)
+    } else {
+        (store.get_min_index(), store.get_max_index())
+    }
+}
+
+// Count of the bins whose index is beyond the collapsed edges of the store.
+fn collapsed_count(store: &dyn Store, bins: impl Iterator<Item = Bin>) -> f64 {
+    let low = store.collapsed_low_index();
+    let high = store.collapsed_high_index();
+    bins.filter(|bin| {
+        low.is_some_and(|low| bin.index < low) || high.is_some_and(|high| bin.index > high)
+    })
+    .map(|bin| bin.count)
+    .sum()
+}
+
+fn store_for_indexes(mut indexes: Vec<i32>) -> Box<dyn Store> {
+    indexes.sort_unstable();
+    indexes.dedup();
+    match (indexes.first(), indexes.last()) {
+        (Some(&min), Some(&max)) if (indexes.len() as i64) * 2 < max as i64 - min as i64 + 1 => {
+            Box::new(AdaptiveStore::new())
+        }
+        _ => Box::new(UnboundedSizeDenseStore::new()),
+    }
+}
+
+fn scale_store(store: &mut dyn Store, factor: f64) {
+    let mut bins = store.get_descending_stream();
+    if store.as_any().is::<UnboundedSizeDenseU64Store>() {
+        // The store rounds the counts it adds, carry the rounding errors over to the next bin so
+        // that the total count is scaled like the counts rather than most bins rounding to zero.
+        let mut remainder = 0.0;
+        for bin in &mut bins {
+            let count = bin.count * factor + remainder;
+            bin.count = count.round();
+            remainder = count - bin.count;
+        }
+    } else {
+        for bin in &mut bins {
+            bin.count *= factor;
+        }
+    }
+    store.clear();
+    store.merge_with(bins);
+}
+
+fn prune_store(store: &mut dyn Store, min_count: f64, redistribute: bool) {
+    if store.is_empty() {
+        return;
+    }
+    let bins: Vec<Bin> = store.get_ascending_iter().collect();
+    let mut retained: Vec<i32> = bins
+        .iter()
+        .filter(|bin| bin.count >= min_count)
+        .map(|bin| bin.index)
+        .collect();
+    if retained.len() == bins.len() {
+        return;
+    }
+    if redistribute && retained.is_empty() {
+        let largest = bins
+            .iter()
+            .max_by(|a, b| a.count.total_cmp(&b.count))
+            .unwrap();
+        retained.push(largest.index);
+    }
+
+    store.retain(&|bin| retained.binary_search(&bin.index).is_ok());
+    if !redistribute {
+        return;
+    }
+    for Bin { index, count } in bins {
+        if retained.binary_search(&index).is_ok() {
+            continue;
+        }
+        let position = retained.partition_point(|retained| *retained < index);
+        let nearest = match (position.checked_sub(1), retained.get(position)) {
+            (Some(below), Some(above)) if above - index < index - retained[below] => *above,
+            (Some(below), _) => retained[below],
+            (None, above) => *above.unwrap(),
+        };
+        store.add(nearest, count);
+    }
+}
+
+/// Asserts that the value of `sketch` at quantile `q` is within `relative_accuracy` of
+/// `expected`, up to floating-point rounding.
+#[cfg(feature = "test-utils")]
+pub fn assert_quantile_within(sketch: &DDSketch, q: f64, expected: f64, relative_accuracy: f64) {
+    let actual = sketch
+        .get_value_at_quantile(q)
+        .unwrap_or_else(|| panic!("No value at quantile {}.", q));
+    assert!(
+        (actual - expected).abs() <= relative_accuracy * expected.abs() * (1.0 + 1e-12),
+        "The value at quantile {} is {}, not within {} of {}.",
+        q,
+        actual,
+        relative_accuracy,
+        expected
+    );
+}
+
+/// Returns the value at quantile `q` of the union of `sketches`, which must share the same index
+/// mapping, by walking their bins in merged index order instead of building a merged sketch.
+pub fn query_combined_quantile(sketches: &[&DDSketch], q: f64) -> Result<Option<f64>, Error> {
+    let Some(first) = sketches.first() else {
+        return Ok(None);
+    };
+    if sketches
+        .iter()
+        .any(|sketch| sketch.index_mapping != first.index_mapping)
+    {
+        return Err(Error::InvalidArgument("Unmatched indexMapping."));
+    }
+    if !(0.0..=1.0).contains(&q) {
+        return Ok(None);
+    }
+    let count: f64 = sketches.iter().map(|sketch| sketch.get_count()).sum();
+    if count <= 0.0 {
+        return Ok(None);
+    }
+
+    let rank = q * (count - 1.0);
+    let mut n: f64 = 0.0;
+    let mut negative_bins: Vec<_> = sketches
+        .iter()
+        .map(|sketch| sketch.negative_value_store.get_descending_iter().peekable())
+        .collect();
+    while let Some(bin) = next_merged_bin(&mut negative_bins, true) {
+        n += bin.count;
+        if n > rank {
+            return Ok(Some(-first.index_mapping.value(bin.index)));
+        }
+    }
+
+    n += sketches.iter().map(|sketch| sketch.zero_count).sum::<f64>();
+    if n > rank {
+        return Ok(Some(0.0));
+    }
+
+    let mut positive_bins: Vec<_> = sketches
+        .iter()
+        .map(|sketch| sketch.positive_value_store.get_ascending_iter().peekable())
+        .collect();
+    while let Some(bin) = next_merged_bin(&mut positive_bins, false) {
+        n += bin.count;
+        if n > rank {
+            return Ok(Some(first.index_mapping.value(bin.index)));
+        }
+    }
+    Ok(None)
+}
+
+// Pops the bins with the next index across the iterators, which are all sorted in the same
+// direction, and returns their combined count.
+fn next_merged_bin(
+    iterators: &mut [std::iter::Peekable<StoreIter<'_>>],
+    descending: bool,
+) -> Option<Bin> {
+    let indexes = iterators
+        .iter_mut()
+        .filter_map(|iterator| iterator.peek().map(|bin| bin.index));
+    let index = if descending {
+        indexes.max()?
+    } else {
+        indexes.min()?
+    };
+    let mut count = 0.0;
+    for iterator in iterators.iter_mut() {
+        while let Some(bin) = iterator.next_if(|bin| bin.index == index) {
+            count += bin.count;
         }
     }
+    Some(Bin { index, count })
 }
 
 // factory methods
 impl DDSketch {
+    pub(crate) fn new(
+        index_mapping: IndexMapping,
+        negative_value_store: Box<dyn Store>,
+        positive_value_store: Box<dyn Store>,
+    ) -> DDSketch {
+        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
+        let max_indexed_value = index_mapping.max_indexable_value();
+        let mut sketch = DDSketch {
+            index_mapping,
+            negative_value_store,
+            positive_value_store,
+            min_indexed_value,
+            max_indexed_value,
+            zero_count: 0.0,
+            sum: 0.0,
+            exact_min: f64::INFINITY,
+            exact_max: f64::NEG_INFINITY,
+            unknown_extremes: false,
+            accept_min: -max_indexed_value,
+            accept_max: max_indexed_value,
+        };
+        sketch.sum = sketch.bins_sum();
+        sketch.unknown_extremes = !sketch.is_empty();
+        sketch
+    }
+
+    /// Sets the index offset so that values close to `value` map near index 0, which shortens
+    /// the encoded bin indexes. It only affects the encoding compactness, not the accuracy, but
+    /// sketches must use the same center value to be merged exactly.
+    pub fn center_value(self, value: f64) -> Result<DDSketch, Error> {
+        if !self.is_empty() {
+            return Err(Error::InvalidArgument(
+                "The center value must be set on an empty sketch.",
+            ));
+        }
+        let index_mapping = self.index_mapping.with_center_value(value)?;
+        let mut sketch = DDSketch::new(
+            index_mapping,
+            self.negative_value_store,
+            self.positive_value_store,
+        );
+        sketch.min_indexed_value = f64::max(self.min_indexed_value, sketch.min_indexed_value);
+        sketch.accept_min = f64::max(self.accept_min, sketch.accept_min);
+        sketch.accept_max = f64::min(self.accept_max, sketch.accept_max);
+        Ok(sketch)
+    }
+
+    /// Counts the values whose absolute value is below `threshold` in the zero bucket rather than
+    /// in the stores. The threshold cannot go below the smallest value the mapping can index, and
+    /// the values already recorded are not moved.
+    pub fn with_zero_threshold(mut self, threshold: f64) -> DDSketch {
+        self.min_indexed_value = f64::max(
+            f64::max(0.0, self.index_mapping.min_indexable_value()),
+            threshold,
+        );
+        self
+    }
+
+    /// Re-bins the sketch with a coarser relative accuracy, adding the representative value of
+    /// every bin into fresh stores of the same kind under the new index mapping.
+    pub fn reduce_accuracy(&mut self, new_relative_accuracy: f64) -> Result<(), Error> {
+        let index_mapping = IndexMapping::with_relative_accuracy(
+            self.index_mapping.layout(),
+            new_relative_accuracy,
+        )?;
+        if index_mapping.gamma() <= self.index_mapping.gamma() {
+            return Err(Error::InvalidArgument(
+                "The new relative accuracy must be coarser than the current one.",
+            ));
+        }
+
+        let mut sketch = DDSketch::new(
+            index_mapping,
+            self.negative_value_store.new_empty_like(),
+            self.positive_value_store.new_empty_like(),
+        );
+        sketch.min_indexed_value = f64::max(
+            self.min_indexed_value,
+            sketch.index_mapping.min_indexable_value(),
+        );
+        sketch.accept_min = f64::max(self.accept_min, sketch.accept_min);
+        sketch.accept_max = f64::min(self.accept_max, sketch.accept_max);
+        for bin in self.negative_value_store.get_ascending_iter() {
+            sketch.accept_with_count(-self.index_mapping.value(bin.index), bin.count);
+        }
+        for bin in self.positive_value_store.get_ascending_iter() {
+            sketch.accept_with_count(self.index_mapping.value(bin.index), bin.count);
+        }
+        sketch.zero_count += self.zero_count;
+        sketch.exact_min = self.exact_min;
+        sketch.exact_max = self.exact_max;
+        sketch.unknown_extremes = self.unknown_extremes;
+        *self = sketch;
+        Ok(())
+    }
+
+    /// Deconstructs the sketch into its index mapping, positive value store, negative value store
+    /// and zero count.
+    pub fn into_parts(self) -> (IndexMapping, Box<dyn Store>, Box<dyn Store>, f64) {
+        (
+            self.index_mapping,
+            self.positive_value_store,
+            self.negative_value_store,
+            self.zero_count,
+        )
+    }
+
+    /// Rebuilds a sketch from the parts returned by `into_parts`, checking that the bins and the
+    /// zero count are valid and that the bin indexes are within the range of the index mapping.
+    pub fn from_parts(
+        index_mapping: IndexMapping,
+        positive_value_store: Box<dyn Store>,
+        negative_value_store: Box<dyn Store>,
+        zero_count: f64,
+    ) -> Result<DDSketch, Error> {
+        let mut sketch = DDSketch::new(index_mapping, negative_value_store, positive_value_store);
+        sketch.zero_count = zero_count;
+        sketch.validate()?;
+
+        // Indexes out of the range of the mapping do not map back to themselves.
+        let is_valid = |index: i32| {
+            let value = sketch.index_mapping.value(index);
+            value >= sketch.min_indexed_value && sketch.index_mapping.index(value) == index
+        };
+        for store in [&sketch.negative_value_store, &sketch.positive_value_store] {
+            if !store.is_empty()
+                && (!is_valid(store.get_min_index()) || !is_valid(store.get_max_index()))
+            {
+                return Err(Error::InvalidArgument(
+                    "The store indexes are out of the range of the index mapping.",
+                ));
+            }
+        }
+        Ok(sketch)
+    }
+
+    /// Moves the bins into collapsing stores of at most `max_num_bins` bins each, collapsing the
+    /// existing bins that do not fit.
+    pub fn with_bounded_stores(
+        self,
+        max_num_bins: usize,
+        collapse: CollapseDirection,
+    ) -> Result<DDSketch, Error> {
+        // A negative value is indexed by its absolute value, so the highest indexes of the
+        // negative store hold the lowest values.
+        let (mut negative_value_store, mut positive_value_store): (Box<dyn Store>, Box<dyn Store>) =
+            match collapse {
+                CollapseDirection::Lowest => (
+                    Box::new(CollapsingHighestDenseStore::with_capacity(max_num_bins)?),
+                    Box::new(CollapsingLowestDenseStore::with_capacity(max_num_bins)?),
+                ),
+                CollapseDirection::Highest => (
+                    Box::new(CollapsingLowestDenseStore::with_capacity(max_num_bins)?),
+                    Box::new(CollapsingHighestDenseStore::with_capacity(max_num_bins)?),
+                ),
+                CollapseDirection::Both => (
+                    Box::new(CollapsingHighestDenseStore::with_capacity(max_num_bins)?),
+                    Box::new(CollapsingHighestDenseStore::with_capacity(max_num_bins)?),
+                ),
+            };
+        self.negative_value_store
+            .drain_into(negative_value_store.as_mut());
+        self.positive_value_store
+            .drain_into(positive_value_store.as_mut());
+
+        let mut sketch = DDSketch::new(
+            self.index_mapping,
+            negative_value_store,
+            positive_value_store,
+        );
+        sketch.min_indexed_value = self.min_indexed_value;
+        sketch.zero_count = self.zero_count;
+        sketch.exact_min = self.exact_min;
+        sketch.exact_max = self.exact_max;
+        sketch.unknown_extremes = self.unknown_extremes;
+        sketch.accept_min = self.accept_min;
+        sketch.accept_max = self.accept_max;
+        Ok(sketch)
+    }
+
     pub fn collapsing_lowest_dense(
         relative_accuracy: f64,
         max_num_bins: usize,
@@ -302,18 +2216,11 @@ impl DDSketch {
         let index_mapping = IndexMapping::with_relative_accuracy(LogCubic, relative_accuracy)?;
         let negative_value_store = CollapsingLowestDenseStore::with_capacity(max_num_bins)?;
         let positive_value_store = CollapsingLowestDenseStore::with_capacity(max_num_bins)?;
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-
-        Ok(DDSketch {
+        Ok(DDSketch::new(
             index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+            Box::new(negative_value_store),
+            Box::new(positive_value_store),
+        ))
     }
 
     pub fn collapsing_highest_dense(
@@ -323,34 +2230,78 @@ impl DDSketch {
         let index_mapping = IndexMapping::with_relative_accuracy(LogCubic, relative_accuracy)?;
         let negative_value_store = CollapsingHighestDenseStore::with_capacity(max_num_bins)?;
         let positive_value_store = CollapsingHighestDenseStore::with_capacity(max_num_bins)?;
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
+        Ok(DDSketch::new(
             index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+            Box::new(negative_value_store),
+            Box::new(positive_value_store),
+        ))
     }
 
     pub fn unbounded_dense(relative_accuracy: f64) -> Result<DDSketch, Error> {
         let index_mapping = IndexMapping::with_relative_accuracy(LogCubic, relative_accuracy)?;
         let negative_value_store = UnboundedSizeDenseStore::new();
         let positive_value_store = UnboundedSizeDenseStore::new();
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
+        Ok(DDSketch::new(
             index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+            Box::new(negative_value_store),
+            Box::new(positive_value_store),
+        ))
+    }
+
+    /// Builds an unbounded dense sketch like `unbounded_dense`, which cannot fail since the
+    /// relative accuracy of the preset is valid.
+    pub fn unbounded_dense_preset(preset: Preset) -> DDSketch {
+        DDSketch::unbounded_dense(preset.relative_accuracy())
+            .expect("the relative accuracy of a preset is valid")
+    }
+
+    /// Creates a sketch whose bins hold integer counts, which stay exact beyond 2^53. Counts
+    /// passed to `accept_with_count` are rounded to the nearest integer.
+    pub fn unbounded_dense_u64(relative_accuracy: f64) -> Result<DDSketch, Error> {
+        let index_mapping = IndexMapping::with_relative_accuracy(LogCubic, relative_accuracy)?;
+        let negative_value_store = UnboundedSizeDenseU64Store::new();
+        let positive_value_store = UnboundedSizeDenseU64Store::new();
+        Ok(DDSketch::new(
+            index_mapping,
+            Box::new(negative_value_store),
+            Box::new(positive_value_store),
+        ))
+    }
+
+    pub fn adaptive(relative_accuracy: f64) -> Result<DDSketch, Error> {
+        let index_mapping = IndexMapping::with_relative_accuracy(LogCubic, relative_accuracy)?;
+        let negative_value_store = AdaptiveStore::new();
+        let positive_value_store = AdaptiveStore::new();
+        Ok(DDSketch::new(
+            index_mapping,
+            Box::new(negative_value_store),
+            Box::new(positive_value_store),
+        ))
+    }
+
+    /// Builds a sketch from `values`, scanning them first to pick for each sign a dense store if
+    /// their bins populate at least half of their index span, and a sparse one otherwise.
+    pub fn from_values_auto(relative_accuracy: f64, values: &[f64]) -> Result<DDSketch, Error> {
+        let mut sketch = DDSketch::unbounded_dense(relative_accuracy)?;
+        let mut negative_indexes = Vec::new();
+        let mut positive_indexes = Vec::new();
+        for &value in values {
+            let magnitude = value.abs();
+            if magnitude >= sketch.min_indexed_value && magnitude <= sketch.max_indexed_value {
+                let index = sketch.index_mapping.index(magnitude);
+                if value > 0.0 {
+                    positive_indexes.push(index);
+                } else {
+                    negative_indexes.push(index);
+                }
+            }
+        }
+        sketch.negative_value_store = store_for_indexes(negative_indexes);
+        sketch.positive_value_store = store_for_indexes(positive_indexes);
+        for &value in values {
+            sketch.accept(value);
+        }
+        Ok(sketch)
     }
 
     pub fn logarithmic_collapsing_lowest_dense(
@@ -360,17 +2311,11 @@ impl DDSketch {
         let index_mapping = IndexMapping::with_relative_accuracy(LOG, relative_accuracy)?;
         let negative_value_store = CollapsingLowestDenseStore::with_capacity(max_num_bins)?;
         let positive_value_store = CollapsingLowestDenseStore::with_capacity(max_num_bins)?;
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
+        Ok(DDSketch::new(
             index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+            Box::new(negative_value_store),
+            Box::new(positive_value_store),
+        ))
     }
 
     pub fn logarithmic_collapsing_highest_dense(
@@ -380,17 +2325,11 @@ impl DDSketch {
         let index_mapping = IndexMapping::with_relative_accuracy(LOG, relative_accuracy)?;
         let negative_value_store = CollapsingHighestDenseStore::with_capacity(max_num_bins)?;
         let positive_value_store = CollapsingHighestDenseStore::with_capacity(max_num_bins)?;
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
+        Ok(DDSketch::new(
             index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+            Box::new(negative_value_store),
+            Box::new(positive_value_store),
+        ))
     }
 
     pub fn logarithmic_unbounded_size_dense_store(
@@ -399,17 +2338,90 @@ impl DDSketch {
         let index_mapping = IndexMapping::with_relative_accuracy(LOG, relative_accuracy)?;
         let negative_value_store = UnboundedSizeDenseStore::new();
         let positive_value_store = UnboundedSizeDenseStore::new();
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
+        Ok(DDSketch::new(
             index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+            Box::new(negative_value_store),
+            Box::new(positive_value_store),
+        ))
+    }
+
+    pub fn from_values(relative_accuracy: f64, values: &[f64]) -> Result<DDSketch, Error> {
+        let mut sketch = DDSketch::unbounded_dense(relative_accuracy)?;
+        sketch.extend(values.iter().copied().filter(|value| value.is_finite()));
+        Ok(sketch)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_csv_reader<R: Read>(
+        relative_accuracy: f64,
+        reader: R,
+        value_col: usize,
+        count_col: Option<usize>,
+    ) -> Result<DDSketch, Error> {
+        let mut sketch = DDSketch::unbounded_dense(relative_accuracy)?;
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let columns: Vec<&str> = line.split(',').map(|column| column.trim()).collect();
+            let value = match columns.get(value_col).map(|column| column.parse::<f64>()) {
+                Some(Ok(value)) if value.is_finite() => value,
+                _ => continue,
+            };
+            let count = match count_col {
+                Some(count_col) => match columns.get(count_col).map(|column| column.parse::<f64>())
+                {
+                    Some(Ok(count)) if count.is_finite() && count >= 0.0 => count,
+                    _ => continue,
+                },
+                None => 1.0,
+            };
+            sketch.accept_with_count(value, count);
+        }
+        Ok(sketch)
+    }
+}
+
+impl DDSketch {
+    pub fn approx_eq(&self, other: &DDSketch, epsilon: f64) -> bool {
+        self.index_mapping == other.index_mapping
+            && (self.zero_count - other.zero_count).abs() <= epsilon
+            && self
+                .negative_value_store
+                .approx_eq(other.negative_value_store.as_ref(), epsilon)
+            && self
+                .positive_value_store
+                .approx_eq(other.positive_value_store.as_ref(), epsilon)
+    }
+}
+
+impl PartialEq for DDSketch {
+    fn eq(&self, other: &DDSketch) -> bool {
+        self.approx_eq(other, 0.0)
+    }
+}
+
+impl QuantileSketch for DDSketch {
+    fn accept(&mut self, value: f64) {
+        DDSketch::accept(self, value)
+    }
+
+    fn get_value_at_quantile(&self, quantile: f64) -> Option<f64> {
+        DDSketch::get_value_at_quantile(self, quantile)
+    }
+
+    fn get_count(&self) -> f64 {
+        DDSketch::get_count(self)
+    }
+
+    fn merge_with(&mut self, other: &Self) -> Result<(), Error> {
+        DDSketch::merge_with(self, other)
+    }
+}
+
+impl Extend<f64> for DDSketch {
+    fn extend<I: IntoIterator<Item = f64>>(&mut self, iter: I) {
+        for value in iter {
+            self.accept(value);
+        }
     }
 }
 
@@ -433,6 +2445,10 @@ impl Flag {
         FlagType::value_of(self.marker & 3)
     }
 
+    pub fn is_exact_summary_statistic(&self) -> bool {
+        *self == Flag::COUNT || *self == Flag::SUM || *self == Flag::MIN || *self == Flag::MAX
+    }
+
     pub fn get_marker(&self) -> u8 {
         self.marker
     }
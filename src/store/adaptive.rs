@@ -0,0 +1,238 @@
+use super::*;
+use std::collections::BTreeMap;
+use std::mem;
+
+// A sparse store is promoted to a dense one once it holds at least this many bins and they
+// populate more than half of its index span.
+const PROMOTION_MIN_BINS: usize = 16;
+
+#[derive(Clone)]
+enum Bins {
+    Sparse(BTreeMap<i32, f64>),
+    Dense(UnboundedSizeDenseStore),
+}
+
+#[derive(Clone)]
+pub struct AdaptiveStore {
+    bins: Bins,
+}
+
+impl AdaptiveStore {
+    pub fn new() -> Self {
+        AdaptiveStore {
+            bins: Bins::Sparse(BTreeMap::new()),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_dense(&self) -> bool {
+        matches!(self.bins, Bins::Dense(_))
+    }
+
+    fn promote_if_dense_enough(&mut self) {
+        if let Bins::Sparse(bins) = &self.bins {
+            if bins.len() < PROMOTION_MIN_BINS {
+                return;
+            }
+            let span = self.get_max_index() as i64 - self.get_min_index() as i64 + 1;
+            if (bins.len() as i64) * 2 > span {
+                let mut dense = UnboundedSizeDenseStore::new();
+                for (index, count) in bins {
+                    dense.add(*index, *count);
+                }
+                self.bins = Bins::Dense(dense);
+            }
+        }
+    }
+}
+
+impl Default for AdaptiveStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Store for AdaptiveStore {
+    fn add(&mut self, index: i32, count: f64) {
+        if count <= 0.0 {
+            return;
+        }
+
+        match &mut self.bins {
+            Bins::Sparse(bins) => {
+                *bins.entry(index).or_insert(0.0) += count;
+                self.promote_if_dense_enough();
+            }
+            Bins::Dense(store) => store.add(index, count),
+        }
+    }
+
+    fn add_bin(&mut self, bin: Bin) {
+        if bin.count == 0.0 {
+            return;
+        }
+        self.add(bin.index, bin.count);
+    }
+
+    fn clear(&mut self) {
+        self.bins = Bins::Sparse(BTreeMap::new());
+    }
+
+    fn reserve_index_range(&mut self, min_index: i32, max_index: i32) {
+        if let Bins::Dense(store) = &mut self.bins {
+            store.reserve_index_range(min_index, max_index);
+        }
+    }
+
+    fn new_empty_like(&self) -> Box<dyn Store> {
+        Box::new(AdaptiveStore::new())
+    }
+
+    fn is_empty(&self) -> bool {
+        match &self.bins {
+            Bins::Sparse(bins) => bins.is_empty(),
+            Bins::Dense(store) => store.is_empty(),
+        }
+    }
+
+    fn get_total_count(&self) -> f64 {
+        match &self.bins {
+            Bins::Sparse(bins) => bins.values().sum(),
+            Bins::Dense(store) => store.get_total_count(),
+        }
+    }
+
+    fn get_count_in_range(&self, from_index: i32, to_index: i32) -> f64 {
+        match &self.bins {
+            Bins::Sparse(bins) => {
+                if from_index > to_index {
+                    return 0.0;
+                }
+                bins.range(from_index..=to_index).map(|bin| bin.1).sum()
+            }
+            Bins::Dense(store) => store.get_count_in_range(from_index, to_index),
+        }
+    }
+
+    fn as_slices(&self) -> Option<(i32, &[f64])> {
+        match &self.bins {
+            Bins::Sparse(_) => None,
+            Bins::Dense(store) => store.as_slices(),
+        }
+    }
+
+    fn capacity_bins(&self) -> usize {
+        match &self.bins {
+            Bins::Sparse(bins) => bins.len(),
+            Bins::Dense(store) => store.capacity_bins(),
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        match &self.bins {
+            // Approximates the B-tree nodes with the size of their entries.
+            Bins::Sparse(bins) => {
+                mem::size_of::<Self>() + bins.len() * mem::size_of::<(i32, f64)>()
+            }
+            Bins::Dense(store) => {
+                mem::size_of::<Self>() - mem::size_of::<UnboundedSizeDenseStore>()
+                    + store.memory_usage()
+            }
+        }
+    }
+
+    fn collapsed_low_index(&self) -> Option<i32> {
+        None
+    }
+
+    fn collapsed_high_index(&self) -> Option<i32> {
+        None
+    }
+
+    fn get_offset(&self) -> i32 {
+        match &self.bins {
+            Bins::Sparse(_) => 0,
+            Bins::Dense(store) => store.get_offset(),
+        }
+    }
+
+    fn get_min_index(&self) -> i32 {
+        match &self.bins {
+            Bins::Sparse(bins) => bins.keys().next().copied().unwrap_or(i32::MAX),
+            Bins::Dense(store) => store.get_min_index(),
+        }
+    }
+
+    fn get_max_index(&self) -> i32 {
+        match &self.bins {
+            Bins::Sparse(bins) => bins.keys().next_back().copied().unwrap_or(i32::MIN),
+            Bins::Dense(store) => store.get_max_index(),
+        }
+    }
+
+    fn get_count(&self, i: i32) -> f64 {
+        match &self.bins {
+            Bins::Sparse(bins) => bins.get(&i).copied().unwrap_or(0.0),
+            Bins::Dense(store) => store.get_count(i),
+        }
+    }
+
+    fn get_descending_stream(&self) -> Vec<Bin> {
+        match &self.bins {
+            Bins::Sparse(bins) => bins
+                .iter()
+                .rev()
+                .map(|(index, count)| Bin {
+                    index: *index,
+                    count: *count,
+                })
+                .collect(),
+            Bins::Dense(store) => store.get_descending_stream(),
+        }
+    }
+
+    fn get_ascending_stream(&self) -> Vec<Bin> {
+        match &self.bins {
+            Bins::Sparse(bins) => bins
+                .iter()
+                .map(|(index, count)| Bin {
+                    index: *index,
+                    count: *count,
+                })
+                .collect(),
+            Bins::Dense(store) => store.get_ascending_stream(),
+        }
+    }
+
+    fn get_descending_iter(&self) -> StoreIter<'_> {
+        match &self.bins {
+            Bins::Sparse(bins) => StoreIter::from_sparse(bins.iter(), true),
+            Bins::Dense(store) => store.get_descending_iter(),
+        }
+    }
+
+    fn get_ascending_iter(&self) -> StoreIter<'_> {
+        match &self.bins {
+            Bins::Sparse(bins) => StoreIter::from_sparse(bins.iter(), false),
+            Bins::Dense(store) => store.get_ascending_iter(),
+        }
+    }
+
+    fn get_sum(&self, index_mapping: &IndexMapping) -> f64 {
+        match &self.bins {
+            Bins::Sparse(bins) => bins
+                .iter()
+                .map(|bin| index_mapping.value(*bin.0) * bin.1)
+                .sum(),
+            Bins::Dense(store) => store.get_sum(index_mapping),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
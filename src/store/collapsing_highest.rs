@@ -1,5 +1,6 @@
 use super::*;
 use crate::serde;
+use std::mem;
 
 #[derive(Clone)]
 pub struct CollapsingHighestDenseStore {
@@ -15,10 +16,11 @@ pub struct CollapsingHighestDenseStore {
 
 impl CollapsingHighestDenseStore {
     pub fn with_capacity(capacity: usize) -> Result<Self, Error> {
-        if !(1..=2147483647).contains(&capacity) {
-            return Err(Error::InvalidArgument(
-                "Too large capacity: should be between 1 and 2147483648.",
-            ));
+        if capacity == 0 {
+            return Err(Error::MaxNumBinsTooSmall);
+        }
+        if capacity > MAX_NUM_BINS {
+            return Err(Error::MaxNumBinsTooLarge);
         }
         let max_num_bins = capacity as i32;
         Ok(CollapsingHighestDenseStore {
@@ -66,7 +68,9 @@ impl CollapsingHighestDenseStore {
             self.min_index = new_min_index;
             self.max_index = new_max_index;
             self.adjust(new_min_index, new_max_index);
-        } else if new_min_index >= self.offset && new_max_index < self.offset + self.get_length() {
+        } else if new_min_index >= self.offset
+            && (new_max_index as i64) < self.offset as i64 + self.get_length() as i64
+        {
             self.min_index = new_min_index;
             self.max_index = new_max_index;
         } else {
@@ -81,7 +85,11 @@ impl CollapsingHighestDenseStore {
     }
 
     fn adjust(&mut self, new_min_index: i32, new_max_index: i32) {
-        if new_max_index - new_min_index + 1 > self.get_length() {
+        #[cfg(debug_assertions)]
+        let total_count = self.get_total_count();
+
+        // Widened, as the index range may not fit in an i32.
+        if new_max_index as i64 - new_min_index as i64 + 1 > self.get_length() as i64 {
             // The range of indices is too wide, buckets of lowest indices need to be collapsed.
 
             let new_max_index = new_min_index + self.get_length() - 1;
@@ -120,22 +128,28 @@ impl CollapsingHighestDenseStore {
         } else {
             self.center_counts(new_min_index, new_max_index);
         }
+
+        // Collapsing must add the counts of the collapsed bins, never drop them.
+        #[cfg(debug_assertions)]
+        debug_assert!(
+            (self.get_total_count() - total_count).abs() <= 1e-9 * f64::max(1.0, total_count)
+        );
     }
 
     fn get_new_length(&self, new_min_index: i32, new_max_index: i32) -> i32 {
-        let desired_length = (new_max_index as i64 - new_min_index as i64) as i32 + 1;
-        i32::min(
-            self.max_num_bins,
-            ((desired_length + self.array_length_overhead - 1)
-                / self.array_length_growth_increment
-                + 1)
-                * self.array_length_growth_increment,
-        )
+        let desired_length = new_max_index as i64 - new_min_index as i64 + 1;
+        let growth_increment = self.array_length_growth_increment as i64;
+        i64::min(
+            self.max_num_bins as i64,
+            ((desired_length + self.array_length_overhead as i64 - 1) / growth_increment + 1)
+                * growth_increment,
+        ) as i32
     }
 
     fn center_counts(&mut self, new_min_index: i32, new_max_index: i32) {
-        let middle_index = new_min_index + (new_max_index - new_min_index + 1) / 2;
-        let shift = self.offset + self.get_length() / 2 - middle_index;
+        let middle_index =
+            new_min_index as i64 + (new_max_index as i64 - new_min_index as i64 + 1) / 2;
+        let shift = (self.offset as i64 + self.get_length() as i64 / 2 - middle_index) as i32;
         self.shift_counts(shift);
         self.min_index = new_min_index;
         self.max_index = new_max_index;
@@ -194,14 +208,18 @@ impl CollapsingHighestDenseStore {
             return 0.0;
         }
 
-        let from_array_index = i32::max(from_index - self.offset, 0);
-        let to_array_index = i32::min(to_index - self.offset, self.get_length() - 1) + 1;
-
-        let mut total_count: f64 = 0.0;
-        for array_index in from_array_index..to_array_index {
-            total_count += self.counts[array_index as usize];
+        let from_array_index = i64::max(from_index as i64 - self.offset as i64, 0);
+        let to_array_index = i64::min(
+            to_index as i64 - self.offset as i64,
+            self.get_length() as i64 - 1,
+        ) + 1;
+        if from_array_index >= to_array_index {
+            return 0.0;
         }
-        total_count
+
+        self.counts[from_array_index as usize..to_array_index as usize]
+            .iter()
+            .sum()
     }
 
     fn reset_counts(&mut self, from_index: i32, to_index: i32) {
@@ -210,6 +228,19 @@ impl CollapsingHighestDenseStore {
             self.counts[index as usize] = 0.0;
         }
     }
+
+    fn merge_same(&mut self, other: CollapsingHighestDenseStore) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() && self.max_num_bins == other.max_num_bins {
+            *self = other;
+            return;
+        }
+        for bin in other.get_descending_iter() {
+            self.add_bin(bin);
+        }
+    }
 }
 
 impl Store for CollapsingHighestDenseStore {
@@ -227,19 +258,29 @@ impl Store for CollapsingHighestDenseStore {
         }
     }
 
-    fn add_bin(&mut self, bin: (i32, f64)) {
-        if bin.1 == 0.0 {
+    fn add_bin(&mut self, bin: Bin) {
+        if bin.count == 0.0 {
             return;
         }
-        let array_index = serde::i32_to_usize_exact(self.normalize(bin.0));
+        let array_index = serde::i32_to_usize_exact(self.normalize(bin.index));
         match array_index {
             Ok(index) => {
-                self.counts[index] += bin.1;
+                self.counts[index] += bin.count;
             }
             _ => {}
         }
     }
 
+    fn drain_into(self: Box<Self>, target: &mut dyn Store) {
+        match target
+            .as_any_mut()
+            .downcast_mut::<CollapsingHighestDenseStore>()
+        {
+            Some(target) => target.merge_same(*self),
+            None => merge_store(target, self.as_ref()),
+        }
+    }
+
     fn clear(&mut self) {
         self.counts.fill(0.0);
         self.max_index = i32::MIN;
@@ -248,6 +289,54 @@ impl Store for CollapsingHighestDenseStore {
         self.is_collapsed = false;
     }
 
+    fn clear_and_shrink(&mut self) {
+        self.clear();
+        self.counts = Vec::new();
+    }
+
+    fn reserve_index_range(&mut self, min_index: i32, max_index: i32) {
+        if min_index > max_index || self.is_collapsed {
+            return;
+        }
+        // Only the lowest indexes can be kept, and reserving must not collapse the bins.
+        let max_num_bins = self.max_num_bins as i64;
+        let mut min_index = min_index as i64;
+        let mut max_index = i64::min(max_index as i64, min_index + max_num_bins - 1);
+        if !self.is_empty() {
+            min_index = min_index.max(self.max_index as i64 - max_num_bins + 1);
+            max_index = max_index.min(self.min_index as i64 + max_num_bins - 1);
+        }
+        if min_index <= max_index {
+            // Only grow the array, the store keeps the bins it has.
+            let (current_min_index, current_max_index) = (self.min_index, self.max_index);
+            self.extend_range(min_index as i32, max_index as i32);
+            self.min_index = current_min_index;
+            self.max_index = current_max_index;
+        }
+    }
+
+    fn as_slices(&self) -> Option<(i32, &[f64])> {
+        if self.is_empty() {
+            return Some((0, &[]));
+        }
+        let from = (self.min_index - self.offset) as usize;
+        let to = (self.max_index - self.offset) as usize;
+        Some((self.min_index, &self.counts[from..=to]))
+    }
+
+    fn new_empty_like(&self) -> Box<dyn Store> {
+        Box::new(CollapsingHighestDenseStore {
+            max_num_bins: self.max_num_bins,
+            is_collapsed: false,
+            counts: Vec::new(),
+            offset: 0,
+            min_index: i32::MAX,
+            max_index: i32::MIN,
+            array_length_growth_increment: self.array_length_growth_increment,
+            array_length_overhead: self.array_length_overhead,
+        })
+    }
+
     fn is_empty(&self) -> bool {
         self.max_index < self.min_index
     }
@@ -256,6 +345,26 @@ impl Store for CollapsingHighestDenseStore {
         self.get_total_count_with_range(self.min_index, self.max_index)
     }
 
+    fn get_count_in_range(&self, from_index: i32, to_index: i32) -> f64 {
+        self.get_total_count_with_range(from_index, to_index)
+    }
+
+    fn capacity_bins(&self) -> usize {
+        self.counts.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.counts.capacity() * mem::size_of::<f64>()
+    }
+
+    fn collapsed_low_index(&self) -> Option<i32> {
+        None
+    }
+
+    fn collapsed_high_index(&self) -> Option<i32> {
+        self.is_collapsed.then_some(self.max_index)
+    }
+
     fn get_offset(&self) -> i32 {
         self.offset
     }
@@ -272,13 +381,16 @@ impl Store for CollapsingHighestDenseStore {
         self.counts[i as usize]
     }
 
-    fn get_descending_stream(&self) -> Vec<(i32, f64)> {
+    fn get_descending_stream(&self) -> Vec<Bin> {
         let mut bins = Vec::new();
         let mut index = self.max_index;
         while index >= self.min_index {
             let value = self.counts[(index - self.offset) as usize];
             if value > 0.0 {
-                let bin = (index, value);
+                let bin = Bin {
+                    index,
+                    count: value,
+                };
                 bins.push(bin);
             }
             index -= 1;
@@ -286,13 +398,16 @@ impl Store for CollapsingHighestDenseStore {
         bins
     }
 
-    fn get_ascending_stream(&self) -> Vec<(i32, f64)> {
+    fn get_ascending_stream(&self) -> Vec<Bin> {
         let mut bins = Vec::new();
         let mut index = self.min_index;
         while index <= self.max_index {
             let value = self.counts[(index - self.offset) as usize];
             if value > 0.0 {
-                let bin = (index, value);
+                let bin = Bin {
+                    index,
+                    count: value,
+                };
                 bins.push(bin);
             }
             index -= 1;
@@ -319,4 +434,12 @@ impl Store for CollapsingHighestDenseStore {
             self.counts.as_slice(),
         )
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
@@ -0,0 +1,315 @@
+use super::{Bin, StoreIter};
+use crate::serde;
+
+// The types of the counts held by the unbounded stores.
+pub(crate) trait DenseCount: Copy + Default + PartialOrd {
+    fn to_f64(self) -> f64;
+    fn merge(self, other: Self) -> Self;
+    fn sum(counts: &[Self]) -> f64;
+    fn iter(
+        min_index: i32,
+        max_index: i32,
+        offset: i32,
+        desc: bool,
+        counts: &[Self],
+    ) -> StoreIter<'_>;
+}
+
+impl DenseCount for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn merge(self, other: Self) -> Self {
+        self + other
+    }
+
+    fn sum(counts: &[Self]) -> f64 {
+        counts.iter().sum()
+    }
+
+    fn iter(
+        min_index: i32,
+        max_index: i32,
+        offset: i32,
+        desc: bool,
+        counts: &[Self],
+    ) -> StoreIter<'_> {
+        StoreIter::new(min_index, max_index, offset, desc, counts)
+    }
+}
+
+impl DenseCount for u64 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn merge(self, other: Self) -> Self {
+        self.saturating_add(other)
+    }
+
+    fn sum(counts: &[Self]) -> f64 {
+        counts
+            .iter()
+            .fold(0u64, |sum, count| sum.saturating_add(*count)) as f64
+    }
+
+    fn iter(
+        min_index: i32,
+        max_index: i32,
+        offset: i32,
+        desc: bool,
+        counts: &[Self],
+    ) -> StoreIter<'_> {
+        StoreIter::with_u64_counts(min_index, max_index, offset, desc, counts)
+    }
+}
+
+// The counts of the bins from `min_index` to `max_index`, in an array that starts at the index
+// `offset` and grows on both sides as bins are added, shared by the unbounded stores.
+#[derive(Clone)]
+pub(crate) struct DenseCounts<C> {
+    pub(crate) counts: Vec<C>,
+    pub(crate) offset: i32,
+    pub(crate) min_index: i32,
+    pub(crate) max_index: i32,
+    array_length_overhead: i32,
+    array_length_growth_increment: i32,
+}
+
+impl<C: DenseCount> DenseCounts<C> {
+    pub(crate) fn new() -> Self {
+        DenseCounts {
+            counts: Vec::new(),
+            offset: 0,
+            min_index: i32::MAX,
+            max_index: i32::MIN,
+            array_length_growth_increment: 64,
+            array_length_overhead: 6,
+        }
+    }
+
+    pub(crate) fn add(&mut self, index: i32, count: C) {
+        let array_index = serde::i32_to_usize_exact(self.normalize(index));
+        if let Ok(index) = array_index {
+            self.counts[index] = self.counts[index].merge(count);
+        }
+    }
+
+    fn normalize(&mut self, index: i32) -> i32 {
+        if index < self.min_index || index > self.max_index {
+            self.extend_range(index, index);
+        }
+        index - self.offset
+    }
+
+    fn get_length(&self) -> i32 {
+        self.counts.len() as i32
+    }
+
+    pub(crate) fn extend_range(&mut self, new_min_index: i32, new_max_index: i32) {
+        let new_min_index = new_min_index.min(self.min_index);
+        let new_max_index = new_max_index.max(self.max_index);
+
+        if self.is_empty() {
+            let initial_length = self.get_new_length(new_min_index, new_max_index);
+            if initial_length >= self.get_length() {
+                self.counts.resize(initial_length as usize, C::default());
+            }
+            self.offset = new_min_index;
+            self.min_index = new_min_index;
+            self.max_index = new_max_index;
+            self.adjust(new_min_index, new_max_index);
+        } else if new_min_index >= self.offset && new_max_index < self.offset + self.get_length() {
+            self.min_index = new_min_index;
+            self.max_index = new_max_index;
+        } else {
+            // To avoid shifting too often when nearing the capacity of the array, we may grow it before
+            // we actually reach the capacity.
+            let new_length = self.get_new_length(new_min_index, new_max_index);
+            if new_length > self.get_length() {
+                self.counts.resize(new_length as usize, C::default());
+            }
+            self.adjust(new_min_index, new_max_index);
+        }
+    }
+
+    fn adjust(&mut self, new_min_index: i32, new_max_index: i32) {
+        self.center_counts(new_min_index, new_max_index);
+    }
+
+    fn get_new_length(&self, new_min_index: i32, new_max_index: i32) -> i32 {
+        let desired_length = (new_max_index as i64 - new_min_index as i64) as i32 + 1;
+        ((desired_length + self.array_length_overhead - 1) / self.array_length_growth_increment + 1)
+            * self.array_length_growth_increment
+    }
+
+    fn center_counts(&mut self, new_min_index: i32, new_max_index: i32) {
+        let middle_index = new_min_index + (new_max_index - new_min_index + 1) / 2;
+        let shift = self.offset + self.get_length() / 2 - middle_index;
+        self.shift_counts(shift);
+        self.min_index = new_min_index;
+        self.max_index = new_max_index;
+    }
+
+    fn shift_counts(&mut self, shift: i32) {
+        let min_array_index = self.min_index - self.offset;
+        let max_array_index = self.max_index - self.offset;
+
+        // System.arraycopy(counts, min_array_index, counts, min_array_index + shift, max_array_index - min_array_index + 1);
+        self.array_copy(
+            min_array_index,
+            min_array_index + shift,
+            max_array_index - min_array_index + 1,
+        );
+
+        if shift > 0 {
+            // Arrays.fill(counts, min_array_index, min_array_index + shift, 0);
+            let from = min_array_index;
+            let to = min_array_index + shift;
+            for index in from..to {
+                self.counts[index as usize] = C::default();
+            }
+        } else {
+            // Arrays.fill(counts, max_array_index + 1 + shift, max_array_index + 1, 0);
+            let from = max_array_index + 1 + shift;
+            let to = max_array_index + 1;
+            for index in from..to {
+                self.counts[index as usize] = C::default();
+            }
+        }
+
+        self.offset -= shift;
+    }
+
+    fn array_copy(&mut self, src_pos: i32, dest_pos: i32, length: i32) {
+        if src_pos < dest_pos {
+            let mut offset = length - 1;
+            while offset >= 0 {
+                self.counts[(dest_pos + offset) as usize] =
+                    self.counts[(src_pos + offset) as usize];
+                offset -= 1;
+            }
+        } else if src_pos > dest_pos {
+            let mut offset = 0;
+            while offset < length {
+                self.counts[(dest_pos + offset) as usize] =
+                    self.counts[(src_pos + offset) as usize];
+                offset += 1;
+            }
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.max_index < self.min_index
+    }
+
+    pub(crate) fn get_total_count_with_range(&self, from_index: i32, to_index: i32) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        let from_array_index = i64::max(from_index as i64 - self.offset as i64, 0);
+        let to_array_index = i64::min(
+            to_index as i64 - self.offset as i64,
+            self.get_length() as i64 - 1,
+        ) + 1;
+        if from_array_index >= to_array_index {
+            return 0.0;
+        }
+
+        C::sum(&self.counts[from_array_index as usize..to_array_index as usize])
+    }
+
+    // Adds the counts of `other`, whose array is taken over if these counts are empty.
+    pub(crate) fn merge_same(&mut self, other: DenseCounts<C>) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = other;
+            return;
+        }
+        self.extend_range(other.min_index, other.max_index);
+        for index in other.min_index..other.max_index + 1 {
+            let count = &mut self.counts[(index - self.offset) as usize];
+            *count = count.merge(other.counts[(index - other.offset) as usize]);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.counts.fill(C::default());
+        self.max_index = i32::MIN;
+        self.min_index = i32::MAX;
+        self.offset = 0;
+    }
+
+    pub(crate) fn clear_and_shrink(&mut self) {
+        self.clear();
+        self.counts = Vec::new();
+    }
+
+    pub(crate) fn reserve_index_range(&mut self, min_index: i32, max_index: i32) {
+        if min_index <= max_index {
+            // Only grow the array, the store keeps the bins it has.
+            let (current_min_index, current_max_index) = (self.min_index, self.max_index);
+            self.extend_range(min_index, max_index);
+            self.min_index = current_min_index;
+            self.max_index = current_max_index;
+        }
+    }
+
+    // The counts of the bins from `min_index` to `max_index`.
+    pub(crate) fn bins(&self) -> &[C] {
+        if self.is_empty() {
+            return &[];
+        }
+        let from = (self.min_index - self.offset) as usize;
+        let to = (self.max_index - self.offset) as usize;
+        &self.counts[from..=to]
+    }
+
+    pub(crate) fn get_descending_stream(&self) -> Vec<Bin> {
+        let mut bins = Vec::new();
+        let mut index = self.max_index;
+        while index >= self.min_index {
+            let value = self.counts[(index - self.offset) as usize];
+            if value > C::default() {
+                let bin = Bin {
+                    index,
+                    count: value.to_f64(),
+                };
+                bins.push(bin);
+            }
+            index -= 1;
+        }
+        bins
+    }
+
+    pub(crate) fn get_ascending_stream(&self) -> Vec<Bin> {
+        let mut bins = Vec::new();
+        let mut index = self.min_index;
+        while index <= self.max_index {
+            let value = self.counts[(index - self.offset) as usize];
+            if value > C::default() {
+                let bin = Bin {
+                    index,
+                    count: value.to_f64(),
+                };
+                bins.push(bin);
+            }
+            index += 1;
+        }
+        bins
+    }
+
+    pub(crate) fn iter(&self, desc: bool) -> StoreIter<'_> {
+        C::iter(
+            self.min_index,
+            self.max_index,
+            self.offset,
+            desc,
+            self.counts.as_slice(),
+        )
+    }
+}
@@ -2,35 +2,96 @@ use crate::error::Error;
 use crate::input::Input;
 use crate::serde;
 
+mod adaptive;
 mod collapsing_highest;
 mod collapsing_lowest;
+mod dense;
 mod unbounded;
+mod unbounded_u64;
 
 use crate::index_mapping::IndexMapping;
 use crate::output::Output;
 use crate::sketch::{Flag, FlagType};
+pub use adaptive::AdaptiveStore;
 pub use collapsing_highest::CollapsingHighestDenseStore;
 pub use collapsing_lowest::CollapsingLowestDenseStore;
+use std::any::Any;
+use std::collections::{btree_map, BTreeMap};
 pub use unbounded::UnboundedSizeDenseStore;
+pub use unbounded_u64::UnboundedSizeDenseU64Store;
+
+// Adds the bins of `source` to `target`, as a whole slice if `source` holds them contiguously.
+pub(crate) fn merge_store(target: &mut dyn Store, source: &dyn Store) {
+    match source.as_slices() {
+        Some((base, counts)) => target.add_slice(base, counts),
+        None => target.merge_with(source.get_descending_stream()),
+    }
+}
+
+// Keeps the array lengths, once rounded up to the growth increment, within i32.
+pub(crate) const MAX_NUM_BINS: usize = 1 << 30;
 
 pub trait Store {
     fn add(&mut self, index: i32, count: f64);
-    fn add_bin(&mut self, bin: (i32, f64));
-    fn merge_with(&mut self, bins: Vec<(i32, f64)>) {
+    /// Returns the count that `add` records for `count`, which the stores of integer counts round
+    /// to the nearest integer.
+    fn stored_count(&self, count: f64) -> f64 {
+        count
+    }
+    fn add_bin(&mut self, bin: Bin);
+    fn merge_with(&mut self, bins: Vec<Bin>) {
         for bin in bins {
             self.add_bin(bin)
         }
     }
+    /// Returns the index of the lowest bin and the counts of the bins up to the highest one, for
+    /// the stores that hold their counts in a contiguous array.
+    fn as_slices(&self) -> Option<(i32, &[f64])> {
+        None
+    }
+    /// Adds `counts[i]` to the bin at index `base + i`, as `merge_with` would add those bins.
+    fn add_slice(&mut self, base: i32, counts: &[f64]) {
+        for (i, &count) in counts.iter().enumerate().rev() {
+            if count > 0.0 {
+                self.add(base + i as i32, count);
+            }
+        }
+    }
+    /// Moves the bins of this store into `target`. The dense stores hand their counts array over
+    /// to an empty `target` of the same type, and add it to the array of a non-empty one, rather
+    /// than adding their bins one by one.
+    fn drain_into(self: Box<Self>, target: &mut dyn Store) {
+        match self.as_slices() {
+            Some((base, counts)) => target.add_slice(base, counts),
+            None => target.merge_with(self.get_descending_stream()),
+        }
+    }
     fn clear(&mut self);
+    /// Clears the store like `clear`, and also releases the memory allocated for its bins.
+    fn clear_and_shrink(&mut self) {
+        self.clear();
+    }
+    fn reserve_index_range(&mut self, min_index: i32, max_index: i32);
+    fn new_empty_like(&self) -> Box<dyn Store>;
     fn is_empty(&self) -> bool;
     fn get_total_count(&self) -> f64;
+    fn get_count_in_range(&self, from_index: i32, to_index: i32) -> f64;
+    fn capacity_bins(&self) -> usize;
+    fn memory_usage(&self) -> usize;
+    fn collapsed_low_index(&self) -> Option<i32>;
+    fn collapsed_high_index(&self) -> Option<i32>;
     fn get_offset(&self) -> i32;
     fn get_min_index(&self) -> i32;
     fn get_max_index(&self) -> i32;
     fn get_count(&self, i: i32) -> f64;
     fn encode(&self, output: &mut Output, store_flag_type: FlagType) -> Result<(), Error> {
+        self.encode_with_mode(output, store_flag_type, self.best_encoding_mode())
+    }
+    /// Returns the mode that encodes the current bins in the fewest bytes, preferring
+    /// `ContiguousCounts` on ties.
+    fn best_encoding_mode(&self) -> BinEncodingMode {
         if self.is_empty() {
-            return Ok(());
+            return BinEncodingMode::IndexDeltasAndCounts;
         }
 
         let min_index = self.get_min_index();
@@ -44,7 +105,9 @@ pub trait Store {
         dense_encoding_size += serde::signed_var_long_encoded_length(1);
 
         let mut sparse_encoding_size: i64 = 0;
+        let mut index_deltas_encoding_size: i64 = 0;
         let mut num_non_empty_bins: i64 = 0;
+        let mut unit_counts = true;
         let mut previous_index: i64 = 0;
 
         for i in min_index - offset..max_index - offset + 1 {
@@ -53,96 +116,137 @@ pub trait Store {
             dense_encoding_size += count_var_double_encoded_length;
             if count != 0.0 {
                 num_non_empty_bins += 1;
+                unit_counts &= count == 1.0;
                 let index: i64 = offset as i64 + i as i64;
-                sparse_encoding_size +=
+                let index_delta_encoded_length =
                     serde::signed_var_long_encoded_length(index - previous_index);
+                sparse_encoding_size += index_delta_encoded_length;
                 sparse_encoding_size += count_var_double_encoded_length;
+                index_deltas_encoding_size += index_delta_encoded_length;
                 previous_index = index;
             }
         }
+        let num_bins_encoded_length = serde::unsigned_var_long_encoded_length(num_non_empty_bins);
+        sparse_encoding_size += num_bins_encoded_length;
+        index_deltas_encoding_size += num_bins_encoded_length;
 
-        if dense_encoding_size <= sparse_encoding_size {
+        if unit_counts && index_deltas_encoding_size < dense_encoding_size.min(sparse_encoding_size)
+        {
+            BinEncodingMode::IndexDeltas
+        } else if dense_encoding_size <= sparse_encoding_size {
             BinEncodingMode::ContiguousCounts
-                .to_flag(store_flag_type)
-                .encode(output)?;
-            serde::encode_unsigned_var_long(output, num_bins)?;
-            serde::encode_signed_var_long(output, min_index as i64)?;
-            serde::encode_signed_var_long(output, 1)?;
-            for i in min_index - offset..max_index - offset + 1 {
-                serde::encode_var_double(output, self.get_count(i))?;
-            }
         } else {
             BinEncodingMode::IndexDeltasAndCounts
-                .to_flag(store_flag_type)
-                .encode(output)?;
-            serde::encode_unsigned_var_long(output, num_non_empty_bins)?;
-            let mut previous_index = 0;
-            for i in min_index - offset..max_index - offset + 1 {
-                let count = self.get_count(i);
-                if count != 0.0 {
-                    let index: i64 = offset as i64 + i as i64;
-                    serde::encode_signed_var_long(output, index - previous_index)?;
-                    serde::encode_var_double(output, count)?;
-                    previous_index = index;
-                }
-            }
         }
-        Ok(())
     }
-    fn decode_and_merge_with(
-        &mut self,
-        input: &mut Input,
+    /// Encodes the bins with `mode`, falling back to `IndexDeltasAndCounts` when the mode is
+    /// `IndexDeltas` and some bin count is not exactly 1.
+    fn encode_with_mode(
+        &self,
+        output: &mut Output,
+        store_flag_type: FlagType,
         mode: BinEncodingMode,
     ) -> Result<(), Error> {
-        match mode {
-            BinEncodingMode::IndexDeltasAndCounts => {
-                let num_bins = serde::decode_unsigned_var_long(input)?;
-                let mut index: i64 = 0;
-                let mut i = 0;
-                while i < num_bins {
-                    let index_delta = serde::decode_signed_var_long(input)?;
-                    let count = serde::decode_var_double(input)?;
-                    index += index_delta;
-                    self.add(serde::i64_to_i32_exact(index)?, count);
-                    i += 1;
-                }
+        if self.is_empty() {
+            return Ok(());
+        }
 
-                Ok(())
-            }
+        let min_index = self.get_min_index();
+        let max_index = self.get_max_index();
+        let offset = self.get_offset();
+        let array_indexes = min_index - offset..max_index - offset + 1;
 
-            BinEncodingMode::IndexDeltas => {
-                let num_bins = serde::decode_unsigned_var_long(input)?;
-                let mut index: i64 = 0;
-                let mut i = 0;
-                while i < num_bins {
-                    let index_delta = serde::decode_signed_var_long(input)?;
-                    index += index_delta;
-                    self.add(serde::i64_to_i32_exact(index)?, 1.0);
-                    i += 1;
-                }
-                Ok(())
+        let mode = match mode {
+            BinEncodingMode::IndexDeltas
+                if array_indexes.clone().any(|i| {
+                    let count = self.get_count(i);
+                    count != 0.0 && count != 1.0
+                }) =>
+            {
+                BinEncodingMode::IndexDeltasAndCounts
             }
+            mode => mode,
+        };
 
+        mode.to_flag(store_flag_type).encode(output)?;
+        match mode {
             BinEncodingMode::ContiguousCounts => {
-                let num_bins = serde::decode_unsigned_var_long(input)?;
-                let mut index: i64 = serde::decode_signed_var_long(input)?;
-                let index_delta = serde::decode_signed_var_long(input)?;
-
-                let mut i = 0;
-                while i < num_bins {
-                    let count = serde::decode_var_double(input)?;
-                    self.add(serde::i64_to_i32_exact(index)?, count);
-                    index += index_delta;
-                    i += 1;
+                let num_bins: i64 = max_index as i64 - min_index as i64 + 1;
+                serde::encode_unsigned_var_long(output, num_bins)?;
+                serde::encode_signed_var_long(output, min_index as i64)?;
+                serde::encode_signed_var_long(output, 1)?;
+                for i in array_indexes {
+                    serde::encode_var_double(output, self.get_count(i))?;
+                }
+            }
+            BinEncodingMode::IndexDeltasAndCounts | BinEncodingMode::IndexDeltas => {
+                let num_non_empty_bins = array_indexes
+                    .clone()
+                    .filter(|&i| self.get_count(i) != 0.0)
+                    .count();
+                serde::encode_unsigned_var_long(output, num_non_empty_bins as i64)?;
+                let mut previous_index = 0;
+                for i in array_indexes {
+                    let count = self.get_count(i);
+                    if count != 0.0 {
+                        let index: i64 = offset as i64 + i as i64;
+                        serde::encode_signed_var_long(output, index - previous_index)?;
+                        if let BinEncodingMode::IndexDeltasAndCounts = mode {
+                            serde::encode_var_double(output, count)?;
+                        }
+                        previous_index = index;
+                    }
                 }
-                Ok(())
             }
         }
+        Ok(())
+    }
+    fn decode_and_merge_with(
+        &mut self,
+        input: &mut Input,
+        mode: BinEncodingMode,
+    ) -> Result<(), Error> {
+        mode.decode_bins(input, |index, count| self.add(index, count))
     }
-    fn get_descending_stream(&self) -> Vec<(i32, f64)>;
-    fn get_ascending_stream(&self) -> Vec<(i32, f64)>;
+    fn get_descending_stream(&self) -> Vec<Bin>;
+    fn get_ascending_stream(&self) -> Vec<Bin>;
     fn get_descending_iter(&self) -> StoreIter;
     fn get_ascending_iter(&self) -> StoreIter;
+    fn get_ascending_iter_dense(&self) -> Box<dyn DoubleEndedIterator<Item = Bin> + '_> {
+        let offset = self.get_offset();
+        Box::new(
+            (self.get_min_index()..=self.get_max_index()).map(move |index| Bin {
+                index,
+                count: self.get_count(index - offset),
+            }),
+        )
+    }
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn approx_eq(&self, other: &dyn Store, epsilon: f64) -> bool {
+        if self.is_empty() || other.is_empty() {
+            return self.is_empty() && other.is_empty();
+        }
+        if self.get_min_index() != other.get_min_index()
+            || self.get_max_index() != other.get_max_index()
+        {
+            return false;
+        }
+        let mut bins = self.get_ascending_iter().filter(|bin| bin.count != 0.0);
+        let mut other_bins = other.get_ascending_iter().filter(|bin| bin.count != 0.0);
+        loop {
+            match (bins.next(), other_bins.next()) {
+                (None, None) => return true,
+                (Some(bin), Some(other_bin)) => {
+                    if bin.index != other_bin.index || (bin.count - other_bin.count).abs() > epsilon
+                    {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+    }
     fn get_sum(&self, index_mapping: &IndexMapping) -> f64 {
         let mut sum = 0.0;
         if self.is_empty() {
@@ -163,6 +267,41 @@ pub trait Store {
 
         sum
     }
+    /// Subtracts the counts of the bins from the counts of the matching bins of the store, clamping
+    /// them at zero. Bins that the store does not have are ignored.
+    fn subtract_with(&mut self, bins: Vec<Bin>) {
+        let mut counts: BTreeMap<i32, f64> = self
+            .get_ascending_iter()
+            .map(|bin| (bin.index, bin.count))
+            .collect();
+        for bin in bins {
+            if let Some(current) = counts.get_mut(&bin.index) {
+                *current = f64::max(0.0, *current - bin.count);
+            }
+        }
+        self.clear();
+        self.merge_with(
+            counts
+                .into_iter()
+                .filter(|(_, count)| *count > 0.0)
+                .map(|(index, count)| Bin { index, count })
+                .collect(),
+        );
+    }
+    /// Keeps only the bins for which `f(bin)` returns true.
+    fn retain(&mut self, f: &dyn Fn(Bin) -> bool) {
+        let bins: Vec<Bin> = self.get_ascending_iter().filter(|bin| f(*bin)).collect();
+        self.clear();
+        self.merge_with(bins);
+    }
+}
+
+/// A bin of a store, as yielded by the store iterators. The bins are non-empty, except those of
+/// `get_ascending_iter_dense`, which yields every bin of the index range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bin {
+    pub index: i32,
+    pub count: f64,
 }
 
 pub struct StoreIter<'a> {
@@ -170,7 +309,22 @@ pub struct StoreIter<'a> {
     max_index: i32,
     offset: i32,
     desc: bool,
-    counts: &'a [f64],
+    counts: StoreCounts<'a>,
+    sparse: Option<btree_map::Iter<'a, i32, f64>>,
+}
+
+enum StoreCounts<'a> {
+    F64(&'a [f64]),
+    U64(&'a [u64]),
+}
+
+impl StoreCounts<'_> {
+    fn get(&self, i: i32) -> f64 {
+        match self {
+            StoreCounts::F64(counts) => counts[i as usize],
+            StoreCounts::U64(counts) => counts[i as usize] as f64,
+        }
+    }
 }
 
 impl<'a> StoreIter<'a> {
@@ -186,14 +340,55 @@ impl<'a> StoreIter<'a> {
             min_index,
             max_index,
             offset,
-            counts,
+            counts: StoreCounts::F64(counts),
+            sparse: None,
+        }
+    }
+
+    pub(crate) fn with_u64_counts(
+        min_index: i32,
+        max_index: i32,
+        offset: i32,
+        desc: bool,
+        counts: &'a [u64],
+    ) -> StoreIter<'a> {
+        StoreIter {
+            desc,
+            min_index,
+            max_index,
+            offset,
+            counts: StoreCounts::U64(counts),
+            sparse: None,
+        }
+    }
+
+    pub(crate) fn from_sparse(bins: btree_map::Iter<'a, i32, f64>, desc: bool) -> StoreIter<'a> {
+        StoreIter {
+            desc,
+            min_index: i32::MAX,
+            max_index: i32::MIN,
+            offset: 0,
+            counts: StoreCounts::F64(&[]),
+            sparse: Some(bins),
         }
     }
 }
 
 impl<'a> Iterator for StoreIter<'a> {
-    type Item = (i32, f64);
+    type Item = Bin;
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(bins) = &mut self.sparse {
+            let bin = if self.desc {
+                bins.next_back()
+            } else {
+                bins.next()
+            };
+            return bin.map(|(index, count)| Bin {
+                index: *index,
+                count: *count,
+            });
+        }
+
         if self.desc {
             if self.max_index < self.min_index {
                 return None;
@@ -203,15 +398,15 @@ impl<'a> Iterator for StoreIter<'a> {
             self.max_index -= 1;
 
             while self.max_index >= self.min_index {
-                let count = self.counts[(self.max_index - self.offset) as usize];
+                let count = self.counts.get(self.max_index - self.offset);
                 if count != 0.0 {
                     break;
                 }
                 self.max_index -= 1;
             }
 
-            let count = self.counts[(index - self.offset) as usize];
-            Some((index, count))
+            let count = self.counts.get(index - self.offset);
+            Some(Bin { index, count })
         } else {
             if self.min_index > self.max_index {
                 return None;
@@ -221,19 +416,20 @@ impl<'a> Iterator for StoreIter<'a> {
             self.min_index += 1;
 
             while self.min_index <= self.max_index {
-                let count = self.counts[(self.min_index - self.offset) as usize];
+                let count = self.counts.get(self.min_index - self.offset);
                 if count != 0.0 {
                     break;
                 }
                 self.min_index += 1;
             }
 
-            let count = self.counts[(index - self.offset) as usize];
-            Some((index, count))
+            let count = self.counts.get(index - self.offset);
+            Some(Bin { index, count })
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BinEncodingMode {
     IndexDeltasAndCounts = 1,
     IndexDeltas = 2,
@@ -251,6 +447,83 @@ impl BinEncodingMode {
         }
     }
 
+    // Decodes the bins encoded with this mode, passing each index and count to `f`.
+    pub(crate) fn decode_bins(
+        self,
+        input: &mut Input,
+        mut f: impl FnMut(i32, f64),
+    ) -> Result<(), Error> {
+        match self {
+            BinEncodingMode::IndexDeltasAndCounts => {
+                let num_bins = serde::decode_unsigned_var_long(input)?;
+                let mut index: i64 = 0;
+                let mut i = 0;
+                while i < num_bins {
+                    let index_delta = serde::decode_signed_var_long(input)?;
+                    let count = serde::decode_var_double(input)?;
+                    index += index_delta;
+                    f(serde::i64_to_i32_exact(index)?, count);
+                    i += 1;
+                }
+
+                Ok(())
+            }
+
+            BinEncodingMode::IndexDeltas => {
+                let num_bins = serde::decode_unsigned_var_long(input)?;
+                let mut index: i64 = 0;
+                let mut i = 0;
+                while i < num_bins {
+                    let index_delta = serde::decode_signed_var_long(input)?;
+                    index += index_delta;
+                    f(serde::i64_to_i32_exact(index)?, 1.0);
+                    i += 1;
+                }
+                Ok(())
+            }
+
+            BinEncodingMode::ContiguousCounts => {
+                let num_bins = serde::decode_unsigned_var_long(input)?;
+                let mut index: i64 = serde::decode_signed_var_long(input)?;
+                let index_delta = serde::decode_signed_var_long(input)?;
+
+                let mut i = 0;
+                while i < num_bins {
+                    let count = serde::decode_var_double(input)?;
+                    f(serde::i64_to_i32_exact(index)?, count);
+                    index += index_delta;
+                    i += 1;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn skip_bins(self, input: &mut Input) -> Result<(), Error> {
+        let num_bins = serde::decode_unsigned_var_long(input)?;
+        match self {
+            BinEncodingMode::IndexDeltasAndCounts => {
+                for _ in 0..num_bins {
+                    serde::decode_signed_var_long(input)?;
+                    serde::decode_var_double(input)?;
+                }
+            }
+            BinEncodingMode::IndexDeltas => {
+                for _ in 0..num_bins {
+                    serde::decode_signed_var_long(input)?;
+                }
+            }
+            BinEncodingMode::ContiguousCounts => {
+                serde::decode_signed_var_long(input)?;
+                serde::decode_signed_var_long(input)?;
+                for _ in 0..num_bins {
+                    serde::decode_var_double(input)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn to_flag(self, store_flag_type: FlagType) -> Flag {
         let sub_flag = self as u8;
         Flag::with_type(store_flag_type, sub_flag)
@@ -261,6 +534,29 @@ impl BinEncodingMode {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_collapsing_dense_store_capacity_checks() {
+        assert!(matches!(
+            CollapsingLowestDenseStore::with_capacity(0),
+            Err(Error::MaxNumBinsTooSmall)
+        ));
+        assert!(matches!(
+            CollapsingHighestDenseStore::with_capacity(0),
+            Err(Error::MaxNumBinsTooSmall)
+        ));
+        assert!(matches!(
+            CollapsingLowestDenseStore::with_capacity(usize::MAX - 1),
+            Err(Error::MaxNumBinsTooLarge)
+        ));
+        assert!(matches!(
+            CollapsingHighestDenseStore::with_capacity(usize::MAX - 1),
+            Err(Error::MaxNumBinsTooLarge)
+        ));
+        assert!(CollapsingLowestDenseStore::with_capacity(MAX_NUM_BINS).is_ok());
+        assert!(CollapsingHighestDenseStore::with_capacity(MAX_NUM_BINS).is_ok());
+        assert!(CollapsingLowestDenseStore::with_capacity(MAX_NUM_BINS + 1).is_err());
+    }
+
     #[test]
     fn test_collapsing_lowest_dense_store_add() {
         let mut store = CollapsingLowestDenseStore::with_capacity(10).unwrap();
@@ -302,4 +598,312 @@ mod tests {
         assert_eq!(6, store.get_min_index());
         assert_eq!(20.0, store.get_total_count());
     }
+
+    #[test]
+    fn test_collapsing_lowest_dense_store_get_count_in_range() {
+        let mut store = CollapsingLowestDenseStore::with_capacity(10).unwrap();
+        for i in 0..20 {
+            store.add(i, 1.0);
+        }
+        assert_eq!(20.0, store.get_count_in_range(i32::MIN, i32::MAX));
+        assert_eq!(11.0, store.get_count_in_range(0, 10));
+        assert_eq!(3.0, store.get_count_in_range(15, 17));
+        assert_eq!(0.0, store.get_count_in_range(20, 30));
+        assert_eq!(0.0, store.get_count_in_range(17, 15));
+    }
+
+    #[test]
+    fn test_collapsing_highest_dense_store_get_count_in_range() {
+        let mut store = CollapsingHighestDenseStore::with_capacity(10).unwrap();
+        for i in 0..20 {
+            store.add(i, 1.0);
+        }
+        assert_eq!(20.0, store.get_count_in_range(i32::MIN, i32::MAX));
+        assert_eq!(11.0, store.get_count_in_range(9, 20));
+        assert_eq!(3.0, store.get_count_in_range(2, 4));
+        assert_eq!(0.0, store.get_count_in_range(-10, -1));
+        assert_eq!(0.0, store.get_count_in_range(4, 2));
+    }
+
+    #[test]
+    fn test_unbounded_size_dense_store_get_count_in_range() {
+        let mut store = UnboundedSizeDenseStore::new();
+        assert_eq!(0.0, store.get_count_in_range(i32::MIN, i32::MAX));
+        let indexes = vec![-50, -10, -10, 0, 3, 3, 3, 42, 100];
+        for i in indexes {
+            store.add(i, 1.0);
+        }
+        assert_eq!(9.0, store.get_count_in_range(i32::MIN, i32::MAX));
+        assert_eq!(3.0, store.get_count_in_range(-50, -10));
+        assert_eq!(4.0, store.get_count_in_range(-9, 3));
+        assert_eq!(3.0, store.get_count_in_range(3, 3));
+        assert_eq!(2.0, store.get_count_in_range(4, 1000));
+        assert_eq!(0.0, store.get_count_in_range(101, 1000));
+    }
+
+    #[test]
+    fn test_collapsing_dense_store_capacity_bins() {
+        let mut lowest = CollapsingLowestDenseStore::with_capacity(100).unwrap();
+        let mut highest = CollapsingHighestDenseStore::with_capacity(100).unwrap();
+        assert_eq!(0, lowest.capacity_bins());
+        for i in -5000..5000 {
+            lowest.add(i, 1.0);
+            highest.add(i, 1.0);
+            assert!(lowest.capacity_bins() <= 100);
+            assert!(highest.capacity_bins() <= 100);
+        }
+        assert_eq!(100, lowest.capacity_bins());
+        assert_eq!(100, highest.capacity_bins());
+        assert_eq!(10000.0, lowest.get_total_count());
+        assert_eq!(10000.0, highest.get_total_count());
+    }
+
+    #[test]
+    fn test_unbounded_size_dense_store_capacity_bins() {
+        let mut store = UnboundedSizeDenseStore::new();
+        for i in 0..1000 {
+            store.add(i, 1.0);
+        }
+        assert!(store.capacity_bins() >= 1000);
+    }
+
+    #[test]
+    fn test_store_approx_eq() {
+        let mut store1 = UnboundedSizeDenseStore::new();
+        let mut store2 = CollapsingLowestDenseStore::with_capacity(100).unwrap();
+        assert!(store1.approx_eq(&store2, 0.0));
+        for i in 0..50 {
+            store1.add(i, 1.0);
+            store2.add(i, 1.0 + 1e-12);
+        }
+        assert!(!store1.approx_eq(&store2, 0.0));
+        assert!(store1.approx_eq(&store2, 1e-9));
+        store2.add(50, 1.0);
+        assert!(!store1.approx_eq(&store2, 1e-9));
+    }
+
+    #[test]
+    fn test_adaptive_store_promotion() {
+        let mut adaptive = AdaptiveStore::new();
+        let mut dense = UnboundedSizeDenseStore::new();
+        for index in [-1000, 0, 1000, 5000] {
+            adaptive.add(index, 2.0);
+            dense.add(index, 2.0);
+        }
+        assert!(!adaptive.is_dense());
+        assert_eq!(4, adaptive.capacity_bins());
+        assert_eq!(
+            dense.get_ascending_iter().collect::<Vec<_>>(),
+            adaptive.get_ascending_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            dense.get_descending_iter().collect::<Vec<_>>(),
+            adaptive.get_descending_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            dense.get_count_in_range(0, 1000),
+            adaptive.get_count_in_range(0, 1000)
+        );
+
+        for index in -1000..5000 {
+            adaptive.add(index, 1.0);
+            dense.add(index, 1.0);
+        }
+        assert!(adaptive.is_dense());
+        assert_eq!(dense.get_total_count(), adaptive.get_total_count());
+        assert_eq!(
+            dense.get_descending_stream(),
+            adaptive.get_descending_stream()
+        );
+
+        adaptive.clear();
+        assert!(adaptive.is_empty());
+        assert!(!adaptive.is_dense());
+    }
+
+    #[test]
+    fn test_store_best_encoding_mode() {
+        let mut contiguous = UnboundedSizeDenseStore::new();
+        for index in 0..100 {
+            contiguous.add(index, 2.5);
+        }
+        let mut sparse = AdaptiveStore::new();
+        let mut unit_counts = AdaptiveStore::new();
+        for index in [0, 1000, 100000] {
+            sparse.add(index, 3.0);
+            unit_counts.add(index, 1.0);
+        }
+
+        let encoded_length = |store: &dyn Store, mode: BinEncodingMode| {
+            let mut output = Output::with_capacity(64);
+            store
+                .encode_with_mode(&mut output, FlagType::PositiveStore, mode)
+                .unwrap();
+            output.trim().len()
+        };
+        for (store, expected) in [
+            (&contiguous as &dyn Store, BinEncodingMode::ContiguousCounts),
+            (&sparse, BinEncodingMode::IndexDeltasAndCounts),
+            (&unit_counts, BinEncodingMode::IndexDeltas),
+        ] {
+            assert_eq!(expected, store.best_encoding_mode());
+            for mode in [
+                BinEncodingMode::IndexDeltasAndCounts,
+                BinEncodingMode::IndexDeltas,
+                BinEncodingMode::ContiguousCounts,
+            ] {
+                assert!(encoded_length(store, expected) <= encoded_length(store, mode));
+            }
+        }
+    }
+
+    #[test]
+    fn test_store_ascending_iter_dense() {
+        let mut stores: Vec<Box<dyn Store>> = vec![
+            Box::new(UnboundedSizeDenseStore::new()),
+            Box::new(CollapsingLowestDenseStore::with_capacity(100).unwrap()),
+            Box::new(CollapsingHighestDenseStore::with_capacity(100).unwrap()),
+            Box::new(AdaptiveStore::new()),
+        ];
+        for store in stores.iter_mut() {
+            assert_eq!(0, store.get_ascending_iter_dense().count());
+            store.add(-10, 1.0);
+            store.add(5, 2.0);
+            store.add(20, 3.0);
+            let bins: Vec<Bin> = store.get_ascending_iter_dense().collect();
+            assert_eq!(31, bins.len());
+            for (i, index, count) in [(0, -10, 1.0), (1, -9, 0.0), (15, 5, 2.0), (30, 20, 3.0)] {
+                assert_eq!(Bin { index, count }, bins[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_store_drain_into() {
+        let new_stores: [fn() -> Box<dyn Store>; 5] = [
+            || Box::new(UnboundedSizeDenseStore::new()),
+            || Box::new(UnboundedSizeDenseU64Store::new()),
+            || Box::new(CollapsingLowestDenseStore::with_capacity(50).unwrap()),
+            || Box::new(CollapsingHighestDenseStore::with_capacity(50).unwrap()),
+            || Box::new(AdaptiveStore::new()),
+        ];
+        for new_source in new_stores {
+            for new_target in new_stores {
+                let mut source = new_source();
+                let mut drained = new_target();
+                let mut streamed = new_target();
+                for index in -40..40 {
+                    source.add(index * 3, (index + 41) as f64);
+                    drained.add(index * 2, 1.0);
+                    streamed.add(index * 2, 1.0);
+                }
+                streamed.merge_with(source.get_descending_stream());
+                source.drain_into(drained.as_mut());
+                assert_eq!(
+                    streamed.get_ascending_iter().collect::<Vec<_>>(),
+                    drained.get_ascending_iter().collect::<Vec<_>>()
+                );
+            }
+        }
+
+        // A dense store hands its counts array over to an empty store of the same type.
+        for new_store in &new_stores[..4] {
+            let mut source = new_store();
+            for index in -40..40 {
+                source.add(index, 1.0);
+            }
+            let mut target = new_store();
+            let counts = source.get_ascending_iter().collect::<Vec<_>>();
+            let memory_usage = source.memory_usage();
+            let slice = source.as_slices().map(|(_, counts)| counts.as_ptr());
+            source.drain_into(target.as_mut());
+            assert_eq!(counts, target.get_ascending_iter().collect::<Vec<_>>());
+            assert_eq!(memory_usage, target.memory_usage());
+            assert_eq!(slice, target.as_slices().map(|(_, counts)| counts.as_ptr()));
+        }
+    }
+
+    #[test]
+    fn test_store_as_slices() {
+        let new_stores: [fn() -> Box<dyn Store>; 5] = [
+            || Box::new(UnboundedSizeDenseStore::new()),
+            || Box::new(UnboundedSizeDenseU64Store::new()),
+            || Box::new(CollapsingLowestDenseStore::with_capacity(50).unwrap()),
+            || Box::new(CollapsingHighestDenseStore::with_capacity(50).unwrap()),
+            || Box::new(AdaptiveStore::new()),
+        ];
+        for new_store in new_stores {
+            for new_target in new_stores {
+                let mut store = new_store();
+                for index in -40..40 {
+                    store.add(index * 3, (index + 41) as f64);
+                }
+                if let Some((base, counts)) = store.as_slices() {
+                    let bins: Vec<Bin> = (base..)
+                        .zip(counts)
+                        .filter(|(_, &count)| count > 0.0)
+                        .map(|(index, &count)| Bin { index, count })
+                        .collect();
+                    assert_eq!(store.get_ascending_iter().collect::<Vec<_>>(), bins);
+
+                    let mut sliced = new_target();
+                    let mut streamed = new_target();
+                    for index in -40..40 {
+                        sliced.add(index * 2, 1.0);
+                        streamed.add(index * 2, 1.0);
+                    }
+                    sliced.add_slice(base, counts);
+                    streamed.merge_with(store.get_descending_stream());
+                    assert_eq!(
+                        streamed.get_ascending_iter().collect::<Vec<_>>(),
+                        sliced.get_ascending_iter().collect::<Vec<_>>()
+                    );
+                }
+            }
+        }
+        assert!(UnboundedSizeDenseU64Store::new().as_slices().is_none());
+        assert!(AdaptiveStore::new().as_slices().is_none());
+        assert_eq!(
+            Some((0, &[][..])),
+            UnboundedSizeDenseStore::new().as_slices()
+        );
+    }
+
+    #[test]
+    fn test_unbounded_size_dense_store_memory_usage() {
+        let mut store = UnboundedSizeDenseStore::new();
+        let empty_usage = store.memory_usage();
+        store.add(0, 1.0);
+        let usage = store.memory_usage();
+        assert!(usage > empty_usage);
+        for i in 0..10000 {
+            store.add(i, 1.0);
+        }
+        assert!(store.memory_usage() >= usage + 9000 * std::mem::size_of::<f64>());
+    }
+
+    #[test]
+    fn test_unbounded_size_dense_u64_store_add() {
+        let mut store = UnboundedSizeDenseU64Store::new();
+        let mut expected = UnboundedSizeDenseStore::new();
+        for i in -100..100 {
+            store.add(i * 3, (i % 5 + 1) as f64);
+            expected.add(i * 3, (i % 5 + 1) as f64);
+        }
+        store.add(0, 0.4);
+        assert_eq!(expected.get_total_count(), store.get_total_count());
+        assert_eq!(
+            expected.get_descending_stream(),
+            store.get_descending_stream()
+        );
+        assert_eq!(
+            expected.get_ascending_iter().collect::<Vec<_>>(),
+            store.get_ascending_iter().collect::<Vec<_>>()
+        );
+
+        store.add(1000, 9007199254740992.0);
+        store.add(1000, 1.0);
+        store.add(1000, 1.0);
+        assert_eq!(9007199254740994.0, store.get_count_in_range(1000, 1000));
+    }
 }
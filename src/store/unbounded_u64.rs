@@ -0,0 +1,141 @@
+use super::dense::DenseCounts;
+use super::*;
+use std::mem;
+
+#[derive(Clone)]
+pub struct UnboundedSizeDenseU64Store {
+    dense: DenseCounts<u64>,
+}
+
+impl UnboundedSizeDenseU64Store {
+    pub fn new() -> Self {
+        UnboundedSizeDenseU64Store {
+            dense: DenseCounts::new(),
+        }
+    }
+}
+
+impl Default for UnboundedSizeDenseU64Store {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Store for UnboundedSizeDenseU64Store {
+    fn add(&mut self, index: i32, count: f64) {
+        let count = self.stored_count(count);
+        if count < 1.0 {
+            return;
+        }
+        self.dense.add(index, count as u64);
+    }
+
+    fn stored_count(&self, count: f64) -> f64 {
+        // Counts are rounded to the nearest integer.
+        let count = count.round();
+        if count.is_nan() || count < 1.0 {
+            0.0
+        } else {
+            count
+        }
+    }
+
+    fn add_bin(&mut self, bin: Bin) {
+        self.add(bin.index, bin.count);
+    }
+
+    fn drain_into(self: Box<Self>, target: &mut dyn Store) {
+        match target
+            .as_any_mut()
+            .downcast_mut::<UnboundedSizeDenseU64Store>()
+        {
+            Some(target) => target.dense.merge_same(self.dense),
+            None => merge_store(target, self.as_ref()),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+    }
+
+    fn clear_and_shrink(&mut self) {
+        self.dense.clear_and_shrink();
+    }
+
+    fn reserve_index_range(&mut self, min_index: i32, max_index: i32) {
+        self.dense.reserve_index_range(min_index, max_index);
+    }
+
+    fn new_empty_like(&self) -> Box<dyn Store> {
+        Box::new(UnboundedSizeDenseU64Store::new())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    fn get_total_count(&self) -> f64 {
+        self.dense
+            .get_total_count_with_range(self.dense.min_index, self.dense.max_index)
+    }
+
+    fn get_count_in_range(&self, from_index: i32, to_index: i32) -> f64 {
+        self.dense.get_total_count_with_range(from_index, to_index)
+    }
+
+    fn capacity_bins(&self) -> usize {
+        self.dense.counts.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.dense.counts.capacity() * mem::size_of::<u64>()
+    }
+
+    fn collapsed_low_index(&self) -> Option<i32> {
+        None
+    }
+
+    fn collapsed_high_index(&self) -> Option<i32> {
+        None
+    }
+
+    fn get_min_index(&self) -> i32 {
+        self.dense.min_index
+    }
+
+    fn get_max_index(&self) -> i32 {
+        self.dense.max_index
+    }
+
+    fn get_offset(&self) -> i32 {
+        self.dense.offset
+    }
+
+    fn get_count(&self, i: i32) -> f64 {
+        self.dense.counts[i as usize] as f64
+    }
+
+    fn get_descending_stream(&self) -> Vec<Bin> {
+        self.dense.get_descending_stream()
+    }
+
+    fn get_ascending_stream(&self) -> Vec<Bin> {
+        self.dense.get_ascending_stream()
+    }
+
+    fn get_descending_iter(&self) -> StoreIter<'_> {
+        self.dense.iter(true)
+    }
+
+    fn get_ascending_iter(&self) -> StoreIter<'_> {
+        self.dense.iter(false)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
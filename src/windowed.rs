@@ -0,0 +1,77 @@
+use crate::error::Error;
+use crate::{query_combined_quantile, DDSketch};
+
+/// A ring of sketches, each covering `bucket_millis` milliseconds, for quantiles over the most
+/// recent time windows. Accepting a value in a newer window rotates the ring, dropping the
+/// oldest windows.
+pub struct TimeWindowedSketch {
+    buckets: Vec<DDSketch>,
+    bucket_millis: u64,
+    newest_window: Option<u64>,
+}
+
+impl TimeWindowedSketch {
+    pub fn new(
+        relative_accuracy: f64,
+        bucket_millis: u64,
+        bucket_count: usize,
+    ) -> Result<TimeWindowedSketch, Error> {
+        if bucket_millis == 0 {
+            return Err(Error::InvalidArgument(
+                "The bucket duration must be positive.",
+            ));
+        }
+        if bucket_count == 0 {
+            return Err(Error::InvalidArgument(
+                "The number of buckets must be positive.",
+            ));
+        }
+        let buckets = (0..bucket_count)
+            .map(|_| DDSketch::unbounded_dense(relative_accuracy))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TimeWindowedSketch {
+            buckets,
+            bucket_millis,
+            newest_window: None,
+        })
+    }
+
+    /// Records `value` in the window of `timestamp_millis`. Values older than the oldest window of
+    /// the ring are ignored.
+    pub fn accept_at(&mut self, value: f64, timestamp_millis: u64) {
+        let window = timestamp_millis / self.bucket_millis;
+        let bucket_count = self.buckets.len() as u64;
+        match self.newest_window {
+            Some(newest_window) if window <= newest_window => {
+                if newest_window - window >= bucket_count {
+                    return;
+                }
+            }
+            Some(newest_window) => {
+                let expired = u64::min(window - newest_window, bucket_count);
+                for expired_window in window - expired + 1..=window {
+                    self.buckets[(expired_window % bucket_count) as usize].clear();
+                }
+                self.newest_window = Some(window);
+            }
+            None => self.newest_window = Some(window),
+        }
+        self.buckets[(window % bucket_count) as usize].accept(value);
+    }
+
+    /// Returns the value at quantile `q` over the `window_count` most recent windows, up to the
+    /// whole ring, ending at the newest window a value was accepted in.
+    pub fn query_last(&self, window_count: usize, q: f64) -> Option<f64> {
+        let newest_window = self.newest_window?;
+        let bucket_count = self.buckets.len() as u64;
+        let window_count = u64::min(
+            u64::min(window_count as u64, bucket_count),
+            newest_window + 1,
+        );
+        let buckets: Vec<&DDSketch> = (0..window_count)
+            .map(|age| &self.buckets[((newest_window - age) % bucket_count) as usize])
+            .collect();
+        // The buckets share the same index mapping, so this cannot fail.
+        query_combined_quantile(&buckets, q).ok().flatten()
+    }
+}
@@ -1,6 +1,6 @@
 use protobuf::Message;
 
-use sketches_rust::{DDSketch, proto};
+use sketches_rust::{assert_quantile_within, DDSketch, Error, proto};
 
 #[test]
 pub fn test_proto() {
@@ -23,9 +23,204 @@ pub fn test_proto() {
     println!("Bytes count: {:?}", bytes.len());
     println!("Bytes: {:?}", bytes);
 
-    let mut restored_sketch: DDSketch = proto::ddsketch::DDSketch::parse_from_bytes(&bytes).unwrap().into();
+    let restored_sketch: DDSketch = proto::ddsketch::DDSketch::parse_from_bytes(&bytes).unwrap().into();
 
     assert_eq!(min, restored_sketch.get_min().unwrap());
     assert_eq!(max, restored_sketch.get_max().unwrap());
     assert_eq!(p50, restored_sketch.get_value_at_quantile(0.5).unwrap());
+    assert_quantile_within(&restored_sketch, 0.0, -1.4, 0.001);
+    assert_quantile_within(&restored_sketch, 0.5, 0.7, 0.001);
+    assert_quantile_within(&restored_sketch, 1.0, 5.34, 0.001);
+}
+
+#[test]
+pub fn test_agent_payload() {
+    let mut sketch = DDSketch::unbounded_dense(0.001).unwrap();
+    for i in -100..1000 {
+        sketch.accept(i as f64 * 0.5);
+    }
+
+    let bytes = sketch.to_agent_payload();
+    let restored_sketch = DDSketch::from_agent_payload(&bytes).unwrap();
+
+    assert_eq!(sketch.get_count(), restored_sketch.get_count());
+    for q in [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+        let expected = sketch.get_value_at_quantile(q).unwrap();
+        let actual = restored_sketch.get_value_at_quantile(q).unwrap();
+        assert!((expected - actual).abs() <= expected.abs() * 0.02);
+    }
+
+    let dogsketch =
+        proto::agent_payload::sketch_payload::sketch::Dogsketch::parse_from_bytes(&bytes).unwrap();
+    assert_eq!(1100, dogsketch.cnt);
+    assert!(dogsketch.k.windows(2).all(|keys| keys[0] < keys[1]));
+    assert!(DDSketch::from_agent_payload(&[0xff]).is_err());
+}
+
+#[test]
+pub fn test_agent_payload_merges_keys() {
+    // The bins of a sketch more accurate than the agent mapping share its keys.
+    let mut sketch = DDSketch::unbounded_dense(0.001).unwrap();
+    sketch.accept_with_count(100.0, 0.4);
+    sketch.accept_with_count(100.5, 0.4);
+    sketch.accept_with_count(200.0, 3e9);
+    sketch.accept_with_count(201.0, 3e9);
+
+    let bytes = sketch.to_agent_payload();
+    let dogsketch =
+        proto::agent_payload::sketch_payload::sketch::Dogsketch::parse_from_bytes(&bytes).unwrap();
+    assert_eq!(2, dogsketch.k.len());
+    assert!(dogsketch.k.windows(2).all(|keys| keys[0] < keys[1]));
+    assert_eq!(vec![1, u32::MAX], dogsketch.n);
+}
+
+fn proto_roundtrip(sketch: DDSketch) -> DDSketch {
+    let bytes = proto::ddsketch::DDSketch::from(sketch)
+        .write_to_bytes()
+        .unwrap();
+    proto::ddsketch::DDSketch::parse_from_bytes(&bytes)
+        .unwrap()
+        .into()
+}
+
+#[test]
+pub fn test_proto_negative_heavy() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    let mut expected = DDSketch::unbounded_dense(0.01).unwrap();
+    for i in 1..10000 {
+        let value = -(i as f64).powf(1.5) * 0.01;
+        sketch.accept(value);
+        expected.accept(value);
+    }
+    sketch.accept(3.0);
+    expected.accept(3.0);
+
+    let restored_sketch = proto_roundtrip(sketch);
+    assert!(expected.proto_roundtrip_equivalent(&restored_sketch, 1e-12));
+    for q in [0.0, 0.01, 0.5, 0.99, 1.0] {
+        assert_eq!(
+            expected.get_value_at_quantile(q),
+            restored_sketch.get_value_at_quantile(q)
+        );
+    }
+}
+
+#[test]
+pub fn test_proto_zero_heavy() {
+    let mut sketch = DDSketch::adaptive(0.01).unwrap();
+    let mut expected = DDSketch::adaptive(0.01).unwrap();
+    for i in 0..1000 {
+        let value = if i % 10 == 0 { i as f64 * 1e3 } else { 0.0 };
+        sketch.accept(value);
+        expected.accept(value);
+    }
+    sketch.accept(-1e-3);
+    expected.accept(-1e-3);
+
+    let restored_sketch = proto_roundtrip(sketch);
+    assert_eq!(901.0, restored_sketch.zero_count);
+    assert!(expected.proto_roundtrip_equivalent(&restored_sketch, 1e-12));
+    assert_eq!(expected.get_count(), restored_sketch.get_count());
+    assert_eq!(
+        expected.get_value_at_quantile(0.5),
+        restored_sketch.get_value_at_quantile(0.5)
+    );
+    assert_eq!(expected.get_max(), restored_sketch.get_max());
+    assert!(!expected.proto_roundtrip_equivalent(&DDSketch::adaptive(0.01).unwrap(), 1e-12));
+}
+
+#[test]
+pub fn test_proto_negative_indexes() {
+    for step in [1.001, 1.5] {
+        let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+        let mut expected = DDSketch::unbounded_dense(0.01).unwrap();
+        let mut value = 1e-9;
+        while value < 1e-3 {
+            sketch.accept(-value);
+            expected.accept(-value);
+            value *= step;
+        }
+        let min_index = expected.negative_value_store.get_min_index();
+        assert!(expected.negative_value_store.get_max_index() < -100);
+
+        let sketch_proto = proto::ddsketch::DDSketch::from(sketch);
+        let negative_values = sketch_proto.negativeValues.as_ref().unwrap();
+        if negative_values.binCounts.is_empty() {
+            assert_eq!(min_index, negative_values.contiguousBinIndexOffset);
+        } else {
+            assert!(negative_values.contiguousBinCounts.is_empty());
+        }
+        let bytes = sketch_proto.write_to_bytes().unwrap();
+        let restored_sketch: DDSketch = proto::ddsketch::DDSketch::parse_from_bytes(&bytes)
+            .unwrap()
+            .into();
+
+        assert!(expected.proto_roundtrip_equivalent(&restored_sketch, 0.0));
+        for q in [0.0, 0.01, 0.25, 0.5, 0.75, 0.99, 1.0] {
+            assert_eq!(
+                expected.get_value_at_quantile(q),
+                restored_sketch.get_value_at_quantile(q)
+            );
+        }
+    }
+}
+
+#[test]
+pub fn test_proto_try_from_limits() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    for i in -100..1000 {
+        sketch.accept(i as f64 * 0.5);
+    }
+    let expected_count = sketch.get_count();
+    let sketch_proto = proto::ddsketch::DDSketch::from(sketch);
+    let restored_sketch = DDSketch::try_from_proto(&sketch_proto, 4096).unwrap();
+    assert_eq!(expected_count, restored_sketch.get_count());
+    assert!(DDSketch::try_from_proto(&sketch_proto, 8).is_err());
+
+    let mut huge = sketch_proto.clone();
+    let store = huge.positiveValues.mut_or_insert_default();
+    store.contiguousBinCounts = vec![1.0; 1 << 20];
+    assert!(matches!(
+        DDSketch::try_from_proto(&huge, 1 << 16),
+        Err(Error::InvalidArgument(_))
+    ));
+
+    let mut overflowing = sketch_proto.clone();
+    let store = overflowing.positiveValues.mut_or_insert_default();
+    store.contiguousBinIndexOffset = i32::MAX - 10;
+    store.contiguousBinCounts = vec![1.0; 100];
+    assert!(matches!(
+        DDSketch::try_from_proto(&overflowing, usize::MAX),
+        Err(Error::InvalidArgument(_))
+    ));
+
+    let mut sparse = sketch_proto.clone();
+    let store = sparse.negativeValues.mut_or_insert_default();
+    store.binCounts.insert(i32::MIN, 1.0);
+    store.binCounts.insert(i32::MAX, 1.0);
+    assert!(matches!(
+        DDSketch::try_from_proto(&sparse, 1 << 16),
+        Err(Error::InvalidArgument(_))
+    ));
+
+    let mut invalid_mapping = sketch_proto;
+    invalid_mapping.mapping.mut_or_insert_default().gamma = 0.5;
+    assert!(DDSketch::try_from_proto(&invalid_mapping, 4096).is_err());
+}
+
+#[test]
+pub fn test_agent_payload_summary() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    for i in -100..1000 {
+        sketch.accept(i as f64 * 0.37 + 0.01);
+    }
+
+    let bytes = sketch.to_agent_payload();
+    let dogsketch =
+        proto::agent_payload::sketch_payload::sketch::Dogsketch::parse_from_bytes(&bytes).unwrap();
+    assert_eq!(sketch.get_count() as i64, dogsketch.cnt);
+    assert_eq!(sketch.get_exact_min(), Some(dogsketch.min));
+    assert_eq!(sketch.get_exact_max(), Some(dogsketch.max));
+    assert_eq!(sketch.get_sum(), Some(dogsketch.sum));
+    assert_eq!(sketch.get_average(), Some(dogsketch.avg));
 }
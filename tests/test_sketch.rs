@@ -1,4 +1,11 @@
-use sketches_rust::DDSketch;
+use sketches_rust::output::Output;
+use sketches_rust::{
+    assert_quantile_within, query_combined_quantile, Bin, BinEncodingMode, CollapseDirection,
+    CollapsingHighestDenseStore, CollapsingLowestDenseStore, DDSketch, DecodeLimits, Error,
+    IndexMapping, IndexMappingLayout, MergeReport, Preset, Region, SketchSummary, Store, Summary,
+    TimeWindowedSketch, UnboundedSizeDenseStore,
+};
+use std::io::Cursor;
 
 #[cfg(test)]
 mod test_proto;
@@ -142,6 +149,88 @@ fn test_sketch_merge_2() {
     assert_eq!(300.0, sketch1.get_count());
 }
 
+#[test]
+fn test_sketch_merge_from_1() {
+    let accuracy = 2e-2;
+
+    let mut sketch1 = DDSketch::unbounded_dense(accuracy).unwrap();
+    let mut sketch2 = DDSketch::unbounded_dense(accuracy).unwrap();
+    for i in -99..101 {
+        sketch1.accept(i as f64);
+        sketch2.accept(i as f64);
+    }
+
+    let mut other1 = DDSketch::unbounded_dense(accuracy).unwrap();
+    let mut other2 = DDSketch::unbounded_dense(accuracy).unwrap();
+    for i in 50..300 {
+        other1.accept(i as f64);
+        other2.accept(-i as f64);
+    }
+
+    sketch1.merge_with(&other1).unwrap();
+    sketch1.merge_with(&other2).unwrap();
+    sketch2.merge_from(other1).unwrap();
+    sketch2.merge_from(other2).unwrap();
+
+    assert_eq!(sketch1.get_count(), sketch2.get_count());
+    assert_eq!(sketch1.encode().unwrap(), sketch2.encode().unwrap());
+    for q in [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 1.0] {
+        assert_eq!(
+            sketch1.get_value_at_quantile(q),
+            sketch2.get_value_at_quantile(q)
+        );
+    }
+}
+
+#[test]
+fn test_sketch_merge_from_2() {
+    let accuracy = 2e-2;
+
+    let mut sketch1 = DDSketch::collapsing_lowest_dense(accuracy, 50).unwrap();
+    let mut sketch2 = DDSketch::collapsing_lowest_dense(accuracy, 50).unwrap();
+    for i in -99..101 {
+        sketch1.accept(i as f64);
+        sketch2.accept(i as f64);
+    }
+
+    let mut other1 = DDSketch::unbounded_dense(accuracy).unwrap();
+    let mut other2 = DDSketch::unbounded_dense(accuracy).unwrap();
+    for i in 100..200 {
+        other1.accept(i as f64);
+        other2.accept(i as f64);
+    }
+
+    sketch1.merge_with(&other1).unwrap();
+    sketch2.merge_from(other2).unwrap();
+
+    assert_eq!(300.0, sketch2.get_count());
+    assert_eq!(sketch1.encode().unwrap(), sketch2.encode().unwrap());
+}
+
+#[test]
+fn test_sketch_merge_from_empty() {
+    let accuracy = 2e-2;
+
+    let mut sketch = DDSketch::collapsing_highest_dense(accuracy, 50).unwrap();
+    let mut other = DDSketch::collapsing_highest_dense(accuracy, 50).unwrap();
+    for i in 1..101 {
+        other.accept(i as f64);
+    }
+    let expected = other.encode().unwrap();
+
+    sketch.merge_from(other).unwrap();
+    assert_eq!(100.0, sketch.get_count());
+    assert_eq!(expected, sketch.encode().unwrap());
+}
+
+#[test]
+#[should_panic]
+fn test_sketch_merge_from_panic() {
+    let mut sketch1 = DDSketch::collapsing_lowest_dense(1e-2, 50).unwrap();
+    let sketch2 = DDSketch::collapsing_lowest_dense(2e-2, 50).unwrap();
+    sketch1.merge_from(sketch2).unwrap();
+}
+
 #[test]
 #[should_panic]
 fn test_sketch_merge_panic() {
@@ -158,6 +247,32 @@ fn test_sketch_merge_panic() {
     sketch1.merge_with(&mut sketch2).unwrap();
 }
 
+#[test]
+fn test_sketch_drain() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(2e-2, 50).unwrap();
+    for i in -99..101 {
+        sketch.accept(i as f64);
+    }
+    let expected = sketch.encode().unwrap();
+
+    let drained = sketch.drain();
+    assert_eq!(200.0, drained.get_count());
+    assert_eq!(expected, drained.encode().unwrap());
+    assert!(sketch.is_empty());
+    assert_eq!(0.0, sketch.get_count());
+
+    for i in 1..1001 {
+        sketch.accept(i as f64);
+    }
+    assert_eq!(1000.0, sketch.get_count());
+    assert_eq!(200.0, drained.get_count());
+    let mut other = DDSketch::collapsing_lowest_dense(2e-2, 50).unwrap();
+    for i in 1..1001 {
+        other.accept(i as f64);
+    }
+    assert_eq!(other.encode().unwrap(), sketch.encode().unwrap());
+}
+
 #[test]
 fn test_sketch_decode_1() {
     let accuracy = 2e-2;
@@ -202,7 +317,7 @@ fn test_sketch_decode_4() {
         3, 0, 0, 0, 3, 0, 2, 0, 0, 3, 3, 2, 2, 3, 3, 2, 0, 0, 0, 0, 2, 0, 2, 2, 2, 4, 4, 132, 64,
         0, 4, 2, 0, 2, 2, 3, 132, 64, 4, 132, 64, 4, 2, 2, 0, 6, 4, 6, 132, 64, 2, 6,
     ];
-    let mut sketch = DDSketch::decode(&input).unwrap();
+    let sketch = DDSketch::decode(&input).unwrap();
     assert_eq!(sketch.get_count(), 100.0);
 }
 
@@ -261,3 +376,2460 @@ fn test_sketch_create() {
     let mut sketch6: DDSketch = DDSketch::logarithmic_unbounded_size_dense_store(2e-2).unwrap();
     sketch6.accept(1.0);
 }
+
+#[test]
+fn test_sketch_from_values() {
+    let values = vec![-3.5, -1.0, 0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 100.0];
+    let sketch1 = DDSketch::from_values(2e-2, &values).unwrap();
+    let mut sketch2 = DDSketch::unbounded_dense(2e-2).unwrap();
+    for value in values.iter() {
+        sketch2.accept(*value);
+    }
+    assert_eq!(9.0, sketch1.get_count());
+    assert_eq!(sketch2.encode().unwrap(), sketch1.encode().unwrap());
+
+    let sketch3 = DDSketch::from_values(
+        2e-2,
+        &[1.0, f64::NAN, 2.0, f64::INFINITY, f64::NEG_INFINITY],
+    )
+    .unwrap();
+    assert_eq!(2.0, sketch3.get_count());
+}
+
+#[test]
+fn test_sketch_extend() {
+    let mut sketch1 = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch1.extend((1..101).map(|i| i as f64));
+    let mut sketch2 = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in 1..101 {
+        sketch2.accept(i as f64);
+    }
+    assert_eq!(100.0, sketch1.get_count());
+    assert_eq!(sketch2.encode().unwrap(), sketch1.encode().unwrap());
+}
+
+#[test]
+fn test_sketch_weighted_value_at_quantile() {
+    let mut sketch1 = DDSketch::unbounded_dense(2e-2).unwrap();
+    let mut sketch2 = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in 1..101 {
+        sketch1.accept(i as f64);
+        sketch2.accept(-i as f64);
+    }
+
+    let mut merged = DDSketch::unbounded_dense(2e-2).unwrap();
+    merged.merge_with(&sketch1).unwrap();
+    merged.merge_with(&sketch2).unwrap();
+    for q in [0.0, 0.1, 0.5, 0.9, 1.0] {
+        assert_eq!(
+            merged.get_value_at_quantile(q),
+            DDSketch::weighted_value_at_quantile(&[(&sketch1, 1.0), (&sketch2, 1.0)], q).unwrap()
+        );
+        assert_eq!(
+            sketch1.get_value_at_quantile(q),
+            DDSketch::weighted_value_at_quantile(&[(&sketch1, 1.0), (&sketch2, 0.0)], q).unwrap()
+        );
+    }
+
+    let median =
+        DDSketch::weighted_value_at_quantile(&[(&sketch1, 3.0), (&sketch2, 1.0)], 0.5).unwrap();
+    assert!(median.unwrap() > 0.0);
+
+    assert_eq!(
+        None,
+        DDSketch::weighted_value_at_quantile(&[], 0.5).unwrap()
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_sketch_weighted_value_at_quantile_panic() {
+    let sketch1 = DDSketch::unbounded_dense(1e-2).unwrap();
+    let sketch2 = DDSketch::unbounded_dense(2e-2).unwrap();
+    DDSketch::weighted_value_at_quantile(&[(&sketch1, 1.0), (&sketch2, 1.0)], 0.5).unwrap();
+}
+
+#[test]
+fn test_sketch_validate() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(2e-2, 50).unwrap();
+    sketch.validate().unwrap();
+    for i in -99..101 {
+        sketch.accept(i as f64);
+    }
+    sketch.validate().unwrap();
+
+    sketch.zero_count = -1.0;
+    assert!(sketch.validate().is_err());
+}
+
+#[test]
+fn test_sketch_zero_bucket() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    let min_indexed_value = sketch.min_indexed_value();
+    assert!(min_indexed_value > 0.0);
+
+    sketch.accept(0.0);
+    sketch.accept(min_indexed_value / 2.0);
+    sketch.accept(-min_indexed_value / 2.0);
+    assert_eq!(3.0, sketch.zero_count());
+    assert_eq!(Some(0.0), sketch.get_value_at_quantile(0.5));
+
+    sketch.accept(1.0);
+    assert_eq!(3.0, sketch.zero_count());
+    assert_eq!(4.0, sketch.get_count());
+}
+
+#[test]
+fn test_sketch_min_indexed_value_boundary() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    let min_indexed_value = sketch.min_indexed_value();
+
+    sketch.accept(min_indexed_value);
+    assert_eq!(0.0, sketch.zero_count());
+    assert!(sketch.get_value_at_quantile(0.0).unwrap() > 0.0);
+
+    sketch.accept(-min_indexed_value);
+    assert_eq!(0.0, sketch.zero_count());
+    assert!(sketch.get_value_at_quantile(0.0).unwrap() < 0.0);
+    assert_eq!(2.0, sketch.get_count());
+}
+
+#[test]
+fn test_sketch_accept_with_count() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept_with_count(1.0, 2.5);
+    sketch.accept_with_count(-1.0, 0.5);
+    sketch.accept_with_count(0.0, 3.0);
+    sketch.accept_with_count(2.0, -1.0);
+    assert_eq!(6.0, sketch.get_count());
+    assert_eq!(3.0, sketch.zero_count());
+}
+
+#[test]
+fn test_sketch_accept_with_count_records_the_count() {
+    // Regression test: accept_with_count used to record a count of 1 whatever the count.
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept_with_count(10.0, 4.0);
+    sketch.accept_with_count(-10.0, 2.0);
+    sketch.accept_with_count(0.0, 0.25);
+    sketch.accept(100.0);
+    assert_eq!(
+        4.0,
+        sketch
+            .positive_value_store
+            .get_count_in_range(i32::MIN, sketch.index_of(10.0).unwrap())
+    );
+    assert_eq!(2.0, sketch.negative_value_store.get_total_count());
+    assert_eq!(0.25, sketch.zero_count());
+    assert_eq!(7.25, sketch.get_count());
+    // With the counts recorded, 10 is the median rather than a minority value.
+    assert_quantile_within(&sketch, 0.5, 10.0, 2e-2);
+}
+
+#[test]
+fn test_sketch_from_csv_reader() {
+    let csv = "latency,count\n1.0,2\n2.0,3\nbad,1\n3.0\n-4.0,1.5\n";
+    let sketch = DDSketch::from_csv_reader(2e-2, csv.as_bytes(), 0, Some(1)).unwrap();
+    assert_eq!(6.5, sketch.get_count());
+    assert!((f64::abs(sketch.get_min().unwrap() - -4.0) / 4.0) <= 2e-2);
+    assert!((f64::abs(sketch.get_max().unwrap() - 2.0) / 2.0) <= 2e-2);
+
+    let sketch = DDSketch::from_csv_reader(2e-2, csv.as_bytes(), 0, None).unwrap();
+    assert_eq!(4.0, sketch.get_count());
+}
+
+#[test]
+fn test_sketch_encode_checked() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in 1..6 {
+        sketch.accept(i as f64);
+    }
+    assert_eq!(
+        sketch.encode().unwrap(),
+        sketch.encode_checked(1024).unwrap()
+    );
+
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept(1e-300);
+    sketch.accept(1e300);
+    sketch.accept(-1e-300);
+    sketch.accept(-1e300);
+    let len = sketch.encode().unwrap().len();
+    assert!(sketch.encode_checked(len - 1).is_err());
+    assert_eq!(len, sketch.encode_checked(len).unwrap().len());
+}
+
+#[test]
+fn test_sketch_smoothed_mode_count() {
+    let sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(0, sketch.smoothed_mode_count(3));
+
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for _ in 0..100 {
+        sketch.accept(10.0);
+        sketch.accept(1000.0);
+    }
+    assert_eq!(2, sketch.smoothed_mode_count(1));
+    assert_eq!(2, sketch.smoothed_mode_count(5));
+
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in 0..21 {
+        let value = sketch.index_mapping.value(200 + i);
+        sketch.accept_with_count(value, (10 - i32::abs(10 - i)) as f64 + 1.0);
+    }
+    // Every other bin is bumped to simulate sampling noise.
+    for i in (0..21).step_by(2) {
+        let value = sketch.index_mapping.value(200 + i);
+        sketch.accept_with_count(value, 3.0);
+    }
+    assert!(sketch.smoothed_mode_count(1) > 1);
+    assert_eq!(1, sketch.smoothed_mode_count(5));
+}
+
+#[test]
+fn test_sketch_accept_bounds() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.set_accept_bounds(-100.0, 1000.0).unwrap();
+    sketch.accept(-1000.0);
+    sketch.accept(-100.0);
+    sketch.accept(0.0);
+    sketch.accept(1000.0);
+    sketch.accept(1e300);
+    assert_eq!(3.0, sketch.get_count());
+    assert!(sketch.get_max().unwrap() <= 1000.0 * (1.0 + 2e-2));
+
+    let drained = sketch.drain();
+    assert_eq!(3.0, drained.get_count());
+    sketch.accept(1e300);
+    assert_eq!(0.0, sketch.get_count());
+
+    assert!(sketch.set_accept_bounds(1.0, -1.0).is_err());
+    assert!(sketch.set_accept_bounds(f64::NAN, 1.0).is_err());
+    sketch
+        .set_accept_bounds(f64::NEG_INFINITY, f64::INFINITY)
+        .unwrap();
+    sketch.accept(1e300);
+    assert_eq!(1.0, sketch.get_count());
+}
+
+#[test]
+fn test_sketch_max_serialized_size() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+    assert!(sketch.encode().unwrap().len() <= sketch.max_serialized_size());
+    for i in -100000..100000 {
+        sketch.accept(i as f64 * 1.5);
+    }
+    let max_serialized_size = sketch.max_serialized_size();
+    assert!(sketch.encode().unwrap().len() <= max_serialized_size);
+    assert!(max_serialized_size <= 1 + 8 + 8 + 1 + 9 + 2 * (1 + 8) + 2 * (20 + 9 * 100));
+}
+
+#[cfg(feature = "flate2")]
+#[test]
+fn test_sketch_encode_compressed() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in 0..10000 {
+        sketch.accept((i % 100) as f64);
+    }
+    let compressed = sketch.encode_compressed().unwrap();
+    assert!(compressed.len() < sketch.encode().unwrap().len());
+    let decoded = DDSketch::decode_compressed(&compressed).unwrap();
+    assert_eq!(sketch.get_count(), decoded.get_count());
+    assert_eq!(
+        sketch.get_value_at_quantile(0.5).unwrap(),
+        decoded.get_value_at_quantile(0.5).unwrap()
+    );
+    assert!(DDSketch::decode_compressed(&sketch.encode().unwrap()).is_err());
+}
+
+#[test]
+fn test_sketch_reader_writer() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+    for i in 1..1000 {
+        sketch.accept(i as f64);
+    }
+    let mut cursor = Cursor::new(Vec::new());
+    sketch.encode_to_writer(&mut cursor).unwrap();
+    assert_eq!(sketch.encode().unwrap(), *cursor.get_ref());
+    cursor.set_position(0);
+    let decoded = DDSketch::decode_from_reader(&mut cursor).unwrap();
+    assert_eq!(sketch.get_count(), decoded.get_count());
+    assert_eq!(
+        sketch.get_value_at_quantile(0.9).unwrap(),
+        decoded.get_value_at_quantile(0.9).unwrap()
+    );
+}
+
+#[test]
+fn test_sketch_quantile_estimates() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(None, sketch.quantile_upper_estimate(0.5));
+    for i in -500..=500 {
+        sketch.accept(i as f64);
+    }
+    for q in [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 1.0] {
+        let value = sketch.get_value_at_quantile(q).unwrap();
+        let lower = sketch.quantile_lower_estimate(q).unwrap();
+        let upper = sketch.quantile_upper_estimate(q).unwrap();
+        let expected = -500.0 + q * 1000.0;
+        assert!(lower <= value && value <= upper);
+        assert!(lower <= expected && expected <= upper);
+    }
+    assert_eq!(None, sketch.quantile_upper_estimate(1.1));
+
+    let mut collapsed = DDSketch::collapsing_lowest_dense(2e-2, 10).unwrap();
+    for i in 1..=1000 {
+        collapsed.accept(i as f64);
+    }
+    assert!(collapsed.quantile_lower_estimate(0.0).unwrap() <= 1.0);
+    assert!(collapsed.quantile_upper_estimate(0.0).unwrap() >= 1.0);
+}
+
+#[test]
+fn test_sketch_approx_eq() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+    for i in -100..1000 {
+        sketch.accept(i as f64 * 0.1);
+    }
+    let decoded = DDSketch::decode(&sketch.encode().unwrap()).unwrap();
+    assert!(sketch.approx_eq(&decoded, 1e-9));
+    assert!(sketch == decoded);
+
+    let mut other = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+    other.merge_with(&decoded).unwrap();
+    other.accept(42.0);
+    assert!(!sketch.approx_eq(&other, 1e-9));
+    assert!(sketch != other);
+    assert!(sketch != DDSketch::collapsing_lowest_dense(1e-2, 100).unwrap());
+}
+
+#[test]
+fn test_sketch_decode_summary_only() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in -10..=100 {
+        sketch.accept(i as f64);
+    }
+    let mut bytes = sketch.encode().unwrap();
+    let summary = DDSketch::decode_summary_only(&bytes).unwrap();
+    assert_eq!(None, summary.count);
+    assert_eq!(None, summary.sum);
+    assert_eq!(1.0, summary.zero_count);
+
+    let mut output = Output::with_capacity(16);
+    sketches_rust::serde::encode_var_double(&mut output, 111.0).unwrap();
+    bytes.push(0xa0);
+    bytes.extend(output.trim());
+    for (marker, value) in [(0x84, 4995.0), (0x88, -10.0), (0x8c, 100.0)] {
+        bytes.push(marker);
+        bytes.extend(f64::to_le_bytes(value));
+    }
+    let summary = DDSketch::decode_summary_only(&bytes).unwrap();
+    assert_eq!(
+        SketchSummary {
+            count: Some(111.0),
+            sum: Some(4995.0),
+            min: Some(-10.0),
+            max: Some(100.0),
+            zero_count: 1.0,
+        },
+        summary
+    );
+    assert_eq!(
+        sketch.get_count(),
+        DDSketch::decode(&bytes).unwrap().get_count()
+    );
+    assert!(DDSketch::decode_summary_only(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn test_sketch_variance() {
+    let mut sketch = DDSketch::unbounded_dense(1e-2).unwrap();
+    assert_eq!(None, sketch.get_variance());
+    assert_eq!(None, sketch.get_std_dev());
+
+    // Values -1000..=1000 have a mean of 0 and a population variance of 1000 * 1001 / 3.
+    for i in -1000..=1000 {
+        sketch.accept(i as f64);
+    }
+    let expected = 1000.0 * 1001.0 / 3.0;
+    let variance = sketch.get_variance().unwrap();
+    assert!((variance - expected).abs() <= expected * 2.0 * (2.0 * 1e-2 + 1e-4));
+    let std_dev = sketch.get_std_dev().unwrap();
+    assert!((std_dev - expected.sqrt()).abs() <= expected.sqrt() * 2.0 * 1e-2);
+
+    let mut constant = DDSketch::unbounded_dense(1e-2).unwrap();
+    constant.accept_with_count(5.0, 10.0);
+    assert!(constant.get_variance().unwrap() < 1e-9);
+}
+
+#[test]
+fn test_sketch_center_value() {
+    let mut sketch = DDSketch::unbounded_dense(1e-2).unwrap();
+    let mut centered = DDSketch::unbounded_dense(1e-2)
+        .unwrap()
+        .center_value(20.0)
+        .unwrap();
+    assert!(centered.index_mapping.index(20.0).abs() <= 1);
+    for i in 0..1000 {
+        let value = 15.0 + (i % 100) as f64 * 0.1;
+        sketch.accept(value);
+        centered.accept(value);
+    }
+    for q in [0.0, 0.25, 0.5, 0.75, 1.0] {
+        let expected = sketch.get_value_at_quantile(q).unwrap();
+        let actual = centered.get_value_at_quantile(q).unwrap();
+        assert!((expected - actual).abs() <= expected * 1e-9);
+    }
+    let bytes = centered.encode().unwrap();
+    assert!(bytes.len() < sketch.encode().unwrap().len());
+    assert!(centered == DDSketch::decode(&bytes).unwrap());
+
+    assert!(sketch.center_value(20.0).is_err());
+    assert!(DDSketch::unbounded_dense(1e-2)
+        .unwrap()
+        .center_value(0.0)
+        .is_err());
+
+    // The accept bounds and the zero threshold carry over.
+    let mut sketch = DDSketch::unbounded_dense(1e-2)
+        .unwrap()
+        .with_zero_threshold(1.0);
+    sketch.set_accept_bounds(-10.0, 10.0).unwrap();
+    let mut centered = sketch.center_value(5.0).unwrap();
+    centered.accept(100.0);
+    centered.accept(0.5);
+    centered.accept(5.0);
+    assert_eq!(1.0, centered.zero_count());
+    assert_eq!(1.0, centered.positive_value_store.get_total_count());
+}
+
+#[test]
+fn test_sketch_qq_against() {
+    let mut reference = DDSketch::unbounded_dense(1e-2).unwrap();
+    let mut same = DDSketch::collapsing_lowest_dense(2e-2, 1000).unwrap();
+    let mut shifted = DDSketch::unbounded_dense(1e-2).unwrap();
+    for i in 1..=1000 {
+        reference.accept(i as f64);
+        same.accept(i as f64);
+        shifted.accept(i as f64 * 2.0);
+    }
+    let quantiles = [0.1, 0.5, 0.9, 1.5];
+    let pairs = same.qq_against(&reference, &quantiles);
+    assert_eq!(3, pairs.len());
+    for (expected, actual) in pairs {
+        assert!((expected - actual).abs() <= expected * 4e-2);
+    }
+    for (expected, actual) in shifted.qq_against(&reference, &quantiles) {
+        assert!((expected * 2.0 - actual).abs() <= actual * 3e-2);
+    }
+    let empty = DDSketch::unbounded_dense(1e-2).unwrap();
+    assert!(empty.qq_against(&reference, &quantiles).is_empty());
+}
+
+#[test]
+fn test_sketch_fresh() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(2e-2, 10).unwrap();
+    for i in 1..=1000 {
+        sketch.accept(i as f64);
+    }
+    let mut fresh = sketch.fresh();
+    assert!(fresh.is_empty());
+    assert_eq!(0.0, fresh.get_count());
+    assert_eq!(
+        sketch.index_mapping.relative_accuracy(),
+        fresh.index_mapping.relative_accuracy()
+    );
+    assert_eq!(1000.0, sketch.get_count());
+
+    for i in 1..=1000 {
+        fresh.accept(i as f64);
+    }
+    assert_eq!(sketch.encode().unwrap(), fresh.encode().unwrap());
+}
+
+#[test]
+fn test_sketch_merge_all() {
+    assert!(DDSketch::merge_all(&[]).unwrap().is_none());
+
+    let mut expected = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+    let mut shards = Vec::new();
+    for shard in 0..3 {
+        let mut sketch = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+        for i in 0..500 {
+            let value = (shard * 500 + i) as f64 - 300.0;
+            sketch.accept(value);
+            expected.accept(value);
+        }
+        shards.push(sketch);
+    }
+    let merged = DDSketch::merge_all(&shards).unwrap().unwrap();
+    assert_eq!(expected.get_count(), merged.get_count());
+    for q in [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 1.0] {
+        assert_eq!(
+            expected.get_value_at_quantile(q),
+            merged.get_value_at_quantile(q)
+        );
+    }
+
+    shards.push(DDSketch::collapsing_lowest_dense(1e-2, 100).unwrap());
+    assert!(DDSketch::merge_all(&shards).is_err());
+}
+
+#[test]
+fn test_sketch_summary() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+    assert_eq!(
+        Summary {
+            count: 0.0,
+            sum: None,
+            min: None,
+            max: None,
+            mean: None,
+            quantiles: vec![None, None],
+        },
+        sketch.summary(&[0.5, 0.9])
+    );
+
+    for i in -100..=1000 {
+        sketch.accept(i as f64);
+    }
+    let quantiles = [0.99, 0.0, 0.5, 1.0, 0.25, 1.5, 0.5];
+    let summary = sketch.summary(&quantiles);
+    assert_eq!(sketch.get_count(), summary.count);
+    assert_eq!(sketch.get_sum(), summary.sum);
+    assert_eq!(sketch.get_min(), summary.min);
+    assert_eq!(sketch.get_max(), summary.max);
+    assert_eq!(sketch.get_average(), summary.mean);
+    for (q, value) in quantiles.iter().zip(summary.quantiles) {
+        assert_eq!(sketch.get_value_at_quantile(*q), value);
+    }
+}
+
+#[test]
+fn test_sketch_adaptive() {
+    let mut adaptive = DDSketch::adaptive(2e-2).unwrap();
+    let mut dense = DDSketch::unbounded_dense(2e-2).unwrap();
+    let quantiles = [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 1.0];
+
+    for value in [-1e6, -3.0, 1e-3, 0.0, 42.0, 1e9] {
+        adaptive.accept(value);
+        dense.accept(value);
+    }
+    for q in quantiles {
+        assert_eq!(
+            dense.get_value_at_quantile(q),
+            adaptive.get_value_at_quantile(q)
+        );
+    }
+    assert_eq!(dense.encode().unwrap(), adaptive.encode().unwrap());
+
+    for i in 1..10000 {
+        adaptive.accept(i as f64);
+        dense.accept(i as f64);
+    }
+    for q in quantiles {
+        assert_eq!(
+            dense.get_value_at_quantile(q),
+            adaptive.get_value_at_quantile(q)
+        );
+    }
+    assert_eq!(dense.get_count(), adaptive.get_count());
+    assert_eq!(dense.encode().unwrap(), adaptive.encode().unwrap());
+}
+
+#[test]
+fn test_sketch_error_variants() {
+    assert!(matches!(
+        DDSketch::unbounded_dense(0.0),
+        Err(Error::RelativeAccuracyOutOfRange)
+    ));
+    assert!(matches!(
+        DDSketch::logarithmic_unbounded_size_dense_store(1.0),
+        Err(Error::RelativeAccuracyOutOfRange)
+    ));
+    assert!(matches!(
+        DDSketch::unbounded_dense(f64::NAN),
+        Err(Error::RelativeAccuracyOutOfRange)
+    ));
+    assert!(matches!(
+        DDSketch::collapsing_lowest_dense(0.01, 0),
+        Err(Error::MaxNumBinsTooSmall)
+    ));
+    assert!(matches!(
+        DDSketch::collapsing_highest_dense(0.01, 0),
+        Err(Error::MaxNumBinsTooSmall)
+    ));
+    assert!(matches!(
+        DDSketch::collapsing_lowest_dense(0.01, 2147483648),
+        Err(Error::MaxNumBinsTooLarge)
+    ));
+    assert!(matches!(
+        DDSketch::collapsing_highest_dense(0.01, usize::MAX),
+        Err(Error::MaxNumBinsTooLarge)
+    ));
+}
+
+#[test]
+fn test_sketch_fractional_counts_collapsing() {
+    let mut lowest = DDSketch::collapsing_lowest_dense(2e-2, 50).unwrap();
+    let mut highest = DDSketch::collapsing_highest_dense(2e-2, 50).unwrap();
+    let mut expected = 0.0;
+    for i in 0..10000 {
+        let value = 1.5f64.powi(i % 400 - 200);
+        let count = 0.1 * (i % 7 + 1) as f64 + 1e-3;
+        lowest.accept_with_count(value, count);
+        lowest.accept_with_count(-value, count);
+        highest.accept_with_count(value, count);
+        highest.accept_with_count(-value, count);
+        expected += 2.0 * count;
+    }
+    assert!((lowest.get_count() - expected).abs() < 1e-6);
+    assert!((highest.get_count() - expected).abs() < 1e-6);
+}
+
+#[test]
+fn test_sketch_dense_bins() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(vec![(0.0, 0.0)], sketch.dense_bins().collect::<Vec<_>>());
+
+    sketch.accept(1.0);
+    sketch.accept(100.0);
+    sketch.accept(-10.0);
+    let mapping = &sketch.index_mapping;
+    let positive_bins = (mapping.index(100.0) - mapping.index(1.0) + 1) as usize;
+    let negative_bins = 1;
+    let bins: Vec<(f64, f64)> = sketch.dense_bins().collect();
+    assert_eq!(negative_bins + 1 + positive_bins, bins.len());
+    assert_eq!(3.0, bins.iter().map(|bin| bin.1).sum::<f64>());
+    assert!(bins.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert_eq!(
+        sketch.get_count(),
+        bins.iter().map(|bin| bin.1).sum::<f64>()
+    );
+}
+
+#[test]
+fn test_sketch_memory_usage() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    let empty_usage = sketch.memory_usage();
+    for i in 1..100000 {
+        sketch.accept(i as f64);
+    }
+    assert!(sketch.memory_usage() > empty_usage);
+
+    let mut bounded = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+    for i in 1..100000 {
+        bounded.accept(i as f64);
+    }
+    assert!(bounded.memory_usage() < empty_usage + 200 * std::mem::size_of::<f64>());
+}
+
+#[test]
+fn test_sketch_decode_lenient() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in 0..100 {
+        sketch.accept(i as f64);
+    }
+    let mut bytes = sketch.encode().unwrap();
+    assert!(sketch == DDSketch::decode_strict(&bytes).unwrap());
+    assert!(sketch == DDSketch::decode_lenient(&bytes).unwrap());
+
+    // An unknown sketch feature sub-flag followed by a 3-byte payload.
+    bytes.extend([0x3f << 2, 3, 0xff, 0xff, 0xff]);
+    assert!(DDSketch::decode_strict(&bytes).is_err());
+    assert!(DDSketch::decode(&bytes).is_err());
+    assert!(sketch == DDSketch::decode_lenient(&bytes).unwrap());
+
+    bytes.extend([0x3f << 2, 4, 0xff]);
+    assert!(DDSketch::decode_lenient(&bytes).is_err());
+}
+
+#[test]
+fn test_sketch_merge_with_capacity_hint() {
+    let mut shards = Vec::new();
+    for shard in 0..20 {
+        let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+        for i in 1..200 {
+            sketch.accept((shard * 200 + i) as f64 * if i % 3 == 0 { -1.0 } else { 1.0 });
+        }
+        shards.push(sketch);
+    }
+    let others: Vec<&DDSketch> = shards.iter().collect();
+
+    let mut expected = DDSketch::unbounded_dense(2e-2).unwrap();
+    for other in &others {
+        expected.merge_with(other).unwrap();
+    }
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.merge_with_capacity_hint(&others).unwrap();
+    assert!(expected == sketch);
+    assert_eq!(expected.encode().unwrap(), sketch.encode().unwrap());
+
+    let mut bounded = DDSketch::collapsing_lowest_dense(2e-2, 50).unwrap();
+    let mut expected = DDSketch::collapsing_lowest_dense(2e-2, 50).unwrap();
+    bounded.merge_with_capacity_hint(&others).unwrap();
+    for other in &others {
+        expected.merge_with(other).unwrap();
+    }
+    assert_eq!(expected.get_count(), bounded.get_count());
+    assert_eq!(
+        expected.get_value_at_quantile(0.9),
+        bounded.get_value_at_quantile(0.9)
+    );
+
+    let mut bounded = DDSketch::collapsing_highest_dense(2e-2, 50).unwrap();
+    let mut expected = DDSketch::collapsing_highest_dense(2e-2, 50).unwrap();
+    bounded.merge_with_capacity_hint(&others).unwrap();
+    for other in &others {
+        expected.merge_with(other).unwrap();
+    }
+    assert_eq!(expected.get_count(), bounded.get_count());
+    assert_eq!(
+        expected.get_value_at_quantile(0.1),
+        bounded.get_value_at_quantile(0.1)
+    );
+
+    let other = DDSketch::unbounded_dense(1e-2).unwrap();
+    assert!(sketch.merge_with_capacity_hint(&[&other]).is_err());
+}
+
+#[test]
+fn test_sketch_unbounded_dense_u64() {
+    let mut sketch = DDSketch::unbounded_dense_u64(2e-2).unwrap();
+    let mut float_sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept_with_count(1.0, 9007199254740992.0);
+    float_sketch.accept_with_count(1.0, 9007199254740992.0);
+    for _ in 0..100 {
+        sketch.accept(1.0);
+        float_sketch.accept(1.0);
+    }
+    assert_eq!(9007199254741092.0, sketch.get_count());
+    assert_ne!(9007199254741092.0, float_sketch.get_count());
+
+    let mut sketch = DDSketch::unbounded_dense_u64(2e-2).unwrap();
+    let mut expected = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in -100..1000 {
+        sketch.accept(i as f64);
+        expected.accept(i as f64);
+    }
+    for q in [0.0, 0.1, 0.5, 0.9, 1.0] {
+        assert_eq!(
+            expected.get_value_at_quantile(q),
+            sketch.get_value_at_quantile(q)
+        );
+    }
+    assert_eq!(expected.encode().unwrap(), sketch.encode().unwrap());
+
+    // The sum and the extremes only account for the rounded counts the stores keep.
+    let mut sketch = DDSketch::unbounded_dense_u64(2e-2).unwrap();
+    let mut expected = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept_with_count(10.0, 0.4);
+    sketch.accept_with_count(-10.0, 0.4);
+    sketch.accept_with_count(1.0, 1.0);
+    sketch.accept_with_count(2.0, 1.6);
+    expected.accept_with_count(1.0, 1.0);
+    expected.accept_with_count(2.0, 2.0);
+    assert_eq!(3.0, sketch.get_count());
+    assert_eq!(expected.get_sum(), sketch.get_sum());
+    assert_eq!(Some(1.0), sketch.get_exact_min());
+    assert_eq!(Some(2.0), sketch.get_exact_max());
+}
+
+#[test]
+fn test_sketch_quantile_interpolated() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(None, sketch.get_quantile_interpolated(0.5));
+    for i in 1..=10000 {
+        sketch.accept(i as f64);
+    }
+
+    let mut error = 0.0;
+    let mut interpolated_error = 0.0;
+    for i in 0..=100 {
+        let q = i as f64 / 100.0;
+        let expected = 1.0 + q * 9999.0;
+        let value = sketch.get_value_at_quantile(q).unwrap();
+        let interpolated = sketch.get_quantile_interpolated(q).unwrap();
+        assert!((interpolated - expected).abs() <= expected * 0.021);
+        error += (value - expected).abs() / expected;
+        interpolated_error += (interpolated - expected).abs() / expected;
+    }
+    assert!(interpolated_error < error);
+    assert_eq!(None, sketch.get_quantile_interpolated(1.5));
+}
+
+#[test]
+fn test_sketch_verify_accuracy() {
+    let relative_accuracy = 2e-2;
+    let mut sketch = DDSketch::unbounded_dense(relative_accuracy).unwrap();
+    let mut values: Vec<f64> = (0..5000)
+        .map(|i| ((i * 7919) % 5000) as f64 - 1000.0)
+        .collect();
+    for value in &values {
+        sketch.accept(*value);
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+
+    let quantiles = [0.0, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0];
+    let errors = sketch.verify_accuracy(&values, &quantiles);
+    assert_eq!(quantiles.len(), errors.len());
+    for error in errors {
+        assert!(error <= relative_accuracy + 1e-9);
+    }
+    assert!(sketch.verify_accuracy(&values, &[1.5])[0].is_nan());
+    assert!(sketch.verify_accuracy(&[], &[0.5])[0].is_nan());
+}
+
+#[test]
+fn test_sketch_with_bounded_stores() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in 1..=10000 {
+        sketch.accept(i as f64);
+        sketch.accept(-i as f64);
+    }
+    let bytes = sketch.encode().unwrap();
+
+    for collapse in [
+        CollapseDirection::Lowest,
+        CollapseDirection::Highest,
+        CollapseDirection::Both,
+    ] {
+        let decoded = DDSketch::decode(&bytes).unwrap();
+        let mut bounded = decoded.with_bounded_stores(50, collapse).unwrap();
+        for store in [&bounded.negative_value_store, &bounded.positive_value_store] {
+            assert!(store.capacity_bins() <= 50);
+            assert!(store.get_max_index() - store.get_min_index() < 50);
+        }
+        assert_eq!(20000.0, bounded.get_count());
+
+        for i in 1..=10000 {
+            bounded.accept(i as f64 * 100.0);
+        }
+        assert!(bounded.positive_value_store.capacity_bins() <= 50);
+    }
+
+    let bounded = DDSketch::decode(&bytes)
+        .unwrap()
+        .with_bounded_stores(50, CollapseDirection::Lowest)
+        .unwrap();
+    assert_eq!(
+        sketch.get_value_at_quantile(1.0),
+        bounded.get_value_at_quantile(1.0)
+    );
+    let bounded = DDSketch::decode(&bytes)
+        .unwrap()
+        .with_bounded_stores(50, CollapseDirection::Highest)
+        .unwrap();
+    assert_eq!(
+        sketch.get_value_at_quantile(0.0),
+        bounded.get_value_at_quantile(0.0)
+    );
+    assert!(DDSketch::decode(&bytes)
+        .unwrap()
+        .with_bounded_stores(0, CollapseDirection::Both)
+        .is_err());
+
+    // The zero threshold carries over.
+    let mut bounded = DDSketch::unbounded_dense(2e-2)
+        .unwrap()
+        .with_zero_threshold(1.0)
+        .with_bounded_stores(50, CollapseDirection::Lowest)
+        .unwrap();
+    bounded.accept(0.5);
+    bounded.accept(2.0);
+    assert_eq!(1.0, bounded.zero_count());
+    assert_eq!(1.0, bounded.positive_value_store.get_total_count());
+}
+
+#[test]
+fn test_sketch_queries_through_shared_reference() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in -100..=100 {
+        sketch.accept(i as f64);
+    }
+
+    fn median(sketch: &DDSketch) -> Option<f64> {
+        sketch.get_value_at_quantile(0.5)
+    }
+
+    let shared = &sketch;
+    assert_eq!(201.0, shared.get_count());
+    assert_eq!(Some(0.0), median(shared));
+    assert!((shared.get_min().unwrap() + 100.0).abs() <= 100.0 * 2e-2);
+    assert!((shared.get_max().unwrap() - 100.0).abs() <= 100.0 * 2e-2);
+    assert_eq!(
+        shared.get_value_at_quantile(0.9),
+        shared.summary(&[0.9]).quantiles[0]
+    );
+}
+
+#[test]
+fn test_sketch_prune() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in 1..=1000 {
+        sketch.accept(i as f64);
+        sketch.accept(-(i as f64));
+    }
+    for _ in 0..100 {
+        sketch.accept(500.0);
+        sketch.accept(-500.0);
+    }
+    let count = sketch.get_count();
+    let num_bins = sketch.dense_bins().filter(|bin| bin.1 > 0.0).count();
+    let bytes = sketch.encode().unwrap();
+
+    let mut pruned = DDSketch::decode(&bytes).unwrap();
+    pruned.prune(5.0, false);
+    let pruned_bins = pruned.dense_bins().filter(|bin| bin.1 > 0.0).count();
+    assert!(pruned_bins < num_bins);
+    assert!(pruned.dense_bins().all(|bin| bin.1 == 0.0 || bin.1 >= 5.0));
+    assert!(pruned.get_count() < count);
+
+    let mut redistributed = DDSketch::decode(&bytes).unwrap();
+    redistributed.prune(5.0, true);
+    assert_eq!(
+        pruned_bins,
+        redistributed.dense_bins().filter(|bin| bin.1 > 0.0).count()
+    );
+    assert!((redistributed.get_count() - count).abs() <= 1e-9 * count);
+
+    let mut sparse = DDSketch::unbounded_dense(2e-2).unwrap();
+    sparse.accept(1.0);
+    sparse.accept(100.0);
+    sparse.accept_with_count(1000.0, 2.0);
+    sparse.prune(10.0, true);
+    assert_eq!(4.0, sparse.get_count());
+    assert_eq!(1, sparse.dense_bins().filter(|bin| bin.1 > 0.0).count());
+}
+
+#[test]
+fn test_sketch_to_bytes_from_bytes() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in -50..=100 {
+        sketch.accept(i as f64);
+    }
+
+    let bytes = sketch.to_bytes();
+    assert_eq!(sketch.encode().unwrap(), bytes);
+    let decoded = DDSketch::from_bytes(&bytes).unwrap();
+    assert_eq!(sketch.get_count(), decoded.get_count());
+    for q in [0.0, 0.25, 0.5, 0.99, 1.0] {
+        assert_eq!(
+            sketch.get_value_at_quantile(q),
+            decoded.get_value_at_quantile(q)
+        );
+    }
+
+    match DDSketch::from_bytes(&[0xff]) {
+        Err(error) => {
+            let error: Box<dyn std::error::Error> = error.into();
+            assert!(!error.to_string().is_empty());
+        }
+        Ok(_) => panic!("decoding an invalid sketch should fail"),
+    }
+}
+
+#[test]
+fn test_sketch_error_display_and_equality() {
+    assert!(!format!("{}", Error::InvalidArgument("x")).is_empty());
+    assert_eq!(Error::InvalidArgument("x"), Error::InvalidArgument("x"));
+    assert_ne!(Error::InvalidArgument("x"), Error::InvalidArgument("y"));
+    assert_eq!(
+        Err(Error::MaxNumBinsTooSmall),
+        DDSketch::collapsing_lowest_dense(0.01, 0).map(|sketch| sketch.get_count())
+    );
+
+    let error = Error::RelativeAccuracyOutOfRange;
+    let boxed: Box<dyn std::error::Error + Send + Sync> = Box::new(error.clone());
+    assert_eq!(error.to_string(), boxed.to_string());
+}
+
+#[test]
+fn test_sketch_encode_versioned() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in -10..=100 {
+        sketch.accept(i as f64);
+    }
+
+    let bytes = sketch.encode_versioned();
+    assert_eq!(&sketch.encode().unwrap()[..], &bytes[2..]);
+    let decoded = DDSketch::decode_versioned(&bytes).unwrap();
+    assert_eq!(sketch.get_count(), decoded.get_count());
+    assert_eq!(
+        sketch.get_value_at_quantile(0.5),
+        decoded.get_value_at_quantile(0.5)
+    );
+
+    let mut future = bytes.clone();
+    future[1] += 1;
+    assert!(matches!(
+        DDSketch::decode_versioned(&future),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert!(DDSketch::decode_versioned(&sketch.encode().unwrap()).is_err());
+    assert!(DDSketch::decode_versioned(&[]).is_err());
+}
+
+#[test]
+fn test_sketch_incremental_sum() {
+    fn bins_sum(sketch: &DDSketch) -> f64 {
+        sketch.dense_bins().map(|bin| bin.0 * bin.1).sum()
+    }
+
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(None, sketch.get_sum());
+    for i in -500..=1000 {
+        sketch.accept_with_count(i as f64 * 0.37, 1.5);
+    }
+    let expected = bins_sum(&sketch);
+    assert!((sketch.get_sum().unwrap() - expected).abs() <= 1e-9 * expected.abs());
+
+    let mut other = sketch.fresh();
+    for i in 1..=100 {
+        other.accept(i as f64 * 1e3);
+    }
+    sketch.merge_with(&other).unwrap();
+    let bytes = other.encode().unwrap();
+    sketch.decode_and_merge_with(&bytes).unwrap();
+    let expected = bins_sum(&sketch);
+    assert!((sketch.get_sum().unwrap() - expected).abs() <= 1e-9 * expected.abs());
+
+    let decoded = DDSketch::decode(&sketch.encode().unwrap()).unwrap();
+    assert!(
+        (decoded.get_sum().unwrap() - sketch.get_sum().unwrap()).abs() <= 1e-9 * expected.abs()
+    );
+
+    sketch.clear();
+    assert_eq!(None, sketch.get_sum());
+}
+
+#[test]
+fn test_sketch_get_value_at_rank() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(None, sketch.get_value_at_rank(0.0));
+    for i in -20..=300 {
+        sketch.accept(i as f64);
+    }
+    sketch.accept(0.0);
+
+    let count = sketch.get_count();
+    assert_eq!(sketch.get_min(), sketch.get_value_at_rank(0.0));
+    assert_eq!(sketch.get_max(), sketch.get_value_at_rank(count - 1.0));
+    assert_eq!(
+        sketch.get_value_at_quantile(0.5),
+        sketch.get_value_at_rank(0.5 * (count - 1.0))
+    );
+    assert_eq!(None, sketch.get_value_at_rank(count));
+    assert_eq!(None, sketch.get_value_at_rank(-1.0));
+    assert_eq!(None, sketch.get_value_at_rank(f64::NAN));
+}
+
+#[test]
+fn test_sketch_get_value_at_quantile_fractional_count() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept_with_count(5.0, 0.5);
+    let expected = sketch.get_max();
+    assert!(expected.is_some());
+    for q in [0.0, 0.5, 1.0] {
+        assert_eq!(expected, sketch.get_value_at_quantile(q));
+    }
+
+    sketch.accept_with_count(-5.0, 0.25);
+    assert_eq!(sketch.get_min(), sketch.get_value_at_quantile(1.0));
+}
+
+#[test]
+fn test_sketch_reduce_accuracy() {
+    let mut values: Vec<f64> = (1..=10000).map(|i| i as f64 * 0.1).collect();
+    values.extend((1..=1000).map(|i| -(i as f64)));
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mut sketch = DDSketch::unbounded_dense(1e-3).unwrap();
+    for value in &values {
+        sketch.accept(*value);
+    }
+    sketch.accept(0.0);
+    let num_bins = sketch.dense_bins().filter(|bin| bin.1 > 0.0).count();
+
+    assert!(sketch.reduce_accuracy(1e-3).is_err());
+    assert!(sketch.reduce_accuracy(1e-4).is_err());
+    sketch.reduce_accuracy(2e-2).unwrap();
+    assert!((sketch.index_mapping.relative_accuracy() - 2e-2).abs() <= 1e-12);
+    assert!(sketch.dense_bins().filter(|bin| bin.1 > 0.0).count() < num_bins);
+    assert_eq!(values.len() as f64 + 1.0, sketch.get_count());
+    for error in sketch.verify_accuracy(&values, &[0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0]) {
+        // The two quantizations add up.
+        assert!(error <= 2e-2 + 1e-3 + 1e-9, "{}", error);
+    }
+
+    // The zero threshold carries over.
+    let mut sketch = DDSketch::unbounded_dense(1e-3)
+        .unwrap()
+        .with_zero_threshold(1.0);
+    sketch.accept(0.5);
+    sketch.reduce_accuracy(2e-2).unwrap();
+    sketch.accept(0.5);
+    sketch.accept(2.0);
+    assert_eq!(2.0, sketch.zero_count());
+    assert_eq!(1.0, sketch.positive_value_store.get_total_count());
+}
+
+#[test]
+fn test_sketch_num_bins_and_ranges() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(0, sketch.num_bins());
+    assert_eq!(None, sketch.index_range());
+    assert_eq!(None, sketch.value_range());
+
+    sketch.accept(0.0);
+    assert_eq!(0, sketch.num_bins());
+    assert_eq!(None, sketch.index_range());
+    assert_eq!(Some((0.0, 0.0)), sketch.value_range());
+
+    let mut indexes = std::collections::BTreeSet::new();
+    for i in 1..=1000 {
+        sketch.accept(i as f64);
+        indexes.insert(sketch.index_mapping.index(i as f64));
+    }
+    sketch.accept(-5.0);
+    assert_eq!(indexes.len() + 1, sketch.num_bins());
+    assert_eq!(
+        Some((*indexes.first().unwrap(), *indexes.last().unwrap())),
+        sketch.index_range()
+    );
+    let (min, max) = sketch.value_range().unwrap();
+    assert!((min + 5.0).abs() <= 5.0 * 2e-2);
+    assert!((max - 1000.0).abs() <= 1000.0 * 2e-2);
+}
+
+#[test]
+fn test_sketch_subtract() {
+    let mut a = DDSketch::unbounded_dense(2e-2).unwrap();
+    let mut b = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in -100..=1000 {
+        a.accept(i as f64);
+    }
+    for i in 500..=5000 {
+        b.accept(i as f64);
+        b.accept(-(i as f64));
+    }
+    b.accept(0.0);
+    let quantiles = [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0];
+    let expected = a.summary(&quantiles);
+
+    a.merge_with(&b).unwrap();
+    a.subtract(&b).unwrap();
+    let restored = a.summary(&quantiles);
+    assert_eq!(expected.count, restored.count);
+    assert_eq!(expected.quantiles, restored.quantiles);
+    assert_eq!(expected.min, restored.min);
+    assert_eq!(expected.max, restored.max);
+
+    a.subtract(&b).unwrap();
+    a.subtract(&b).unwrap();
+    assert!(a.dense_bins().all(|bin| bin.1 >= 0.0));
+    assert!(a.get_count() >= 0.0);
+    assert!(a
+        .subtract(&DDSketch::unbounded_dense(1e-2).unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_sketch_into_parts_from_parts() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in -100..=1000 {
+        sketch.accept(i as f64);
+    }
+    let quantiles = [0.0, 0.1, 0.5, 0.9, 1.0];
+    let expected = sketch.summary(&quantiles);
+
+    let (index_mapping, positive_value_store, negative_value_store, zero_count) =
+        sketch.into_parts();
+    assert_eq!(1.0, zero_count);
+    let restored = DDSketch::from_parts(
+        index_mapping.clone(),
+        positive_value_store,
+        negative_value_store,
+        zero_count,
+    )
+    .unwrap();
+    assert_eq!(expected, restored.summary(&quantiles));
+    assert_eq!(index_mapping, restored.index_mapping);
+
+    let empty = || Box::new(UnboundedSizeDenseStore::new());
+    assert!(DDSketch::from_parts(index_mapping.clone(), empty(), empty(), -1.0).is_err());
+    for index in [-1_000_000, 1_000_000] {
+        let mut store = UnboundedSizeDenseStore::new();
+        store.add(index, 1.0);
+        assert!(
+            DDSketch::from_parts(index_mapping.clone(), Box::new(store), empty(), 0.0).is_err()
+        );
+    }
+}
+
+#[test]
+fn test_sketch_with_zero_threshold() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2)
+        .unwrap()
+        .with_zero_threshold(1e-3);
+    assert_eq!(1e-3, sketch.min_indexed_value());
+    let mapping_minimum = DDSketch::unbounded_dense(2e-2).unwrap().min_indexed_value();
+    assert!(mapping_minimum < 1e-6);
+
+    for value in [mapping_minimum * 2.0, 1e-6, 5e-4, -5e-4, 0.0] {
+        sketch.accept(value);
+    }
+    assert_eq!(5.0, sketch.zero_count());
+    assert_eq!(0, sketch.num_bins());
+
+    sketch.accept(1e-3);
+    sketch.accept(-2e-3);
+    assert_eq!(5.0, sketch.zero_count());
+    assert_eq!(2, sketch.num_bins());
+
+    let clamped = DDSketch::unbounded_dense(2e-2)
+        .unwrap()
+        .with_zero_threshold(-1.0);
+    assert_eq!(mapping_minimum, clamped.min_indexed_value());
+}
+
+#[test]
+fn test_sketch_encode_decode_stream() {
+    let mut sketches = Vec::new();
+    for n in [10, 0, 1000] {
+        let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+        for i in 0..n {
+            sketch.accept(i as f64 - 5.0);
+        }
+        sketches.push(sketch);
+    }
+
+    let mut buffer = Vec::new();
+    DDSketch::encode_stream(&sketches, &mut buffer).unwrap();
+    let decoded: Vec<DDSketch> = DDSketch::decode_stream(Cursor::new(&buffer))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(3, decoded.len());
+    for (sketch, decoded) in sketches.iter().zip(decoded.iter()) {
+        assert!(sketch == decoded);
+    }
+
+    assert_eq!(0, DDSketch::decode_stream(Cursor::new(&[])).count());
+    let truncated: Vec<Result<DDSketch, Error>> =
+        DDSketch::decode_stream(Cursor::new(&buffer[..buffer.len() - 1])).collect();
+    assert_eq!(3, truncated.len());
+    assert!(truncated[0].is_ok() && truncated[1].is_ok());
+    assert!(matches!(truncated[2], Err(Error::IoError(_))));
+}
+
+#[test]
+fn test_sketch_get_value_at_upper_quantile() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(None, sketch.get_value_at_upper_quantile(0.5));
+    for i in -100..1000 {
+        sketch.accept(i as f64);
+    }
+    sketch.accept_with_count(0.0, 10.0);
+
+    for upper in [0.0, 0.001, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 1.0] {
+        assert_eq!(
+            sketch.get_value_at_quantile(1.0 - upper),
+            sketch.get_value_at_upper_quantile(upper),
+            "{}",
+            upper
+        );
+    }
+    assert_eq!(None, sketch.get_value_at_upper_quantile(1.5));
+
+    // 1.0 - 1e-17 rounds to 1.0, which asks for the maximum rather than for a value past the ten
+    // largest ones (and the ascending ranks are too coarse at this count to even resolve it).
+    let mut tail = DDSketch::unbounded_dense(2e-2).unwrap();
+    tail.accept_with_count(1.0, 1e18);
+    tail.accept_with_count(1000.0, 10.0);
+    assert_eq!(1.0, 1.0 - 1e-17);
+    assert_ne!(
+        tail.get_value_at_quantile(1.0 - 1e-17),
+        tail.get_value_at_upper_quantile(1e-17)
+    );
+    let upper = tail.get_value_at_upper_quantile(1e-17).unwrap();
+    assert!((upper - 1.0).abs() <= 0.021, "{}", upper);
+}
+
+#[test]
+fn test_sketch_store_bins() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept_with_count(1.0, 2.0);
+    sketch.accept_with_count(100.0, 3.0);
+
+    let index_mapping = &sketch.index_mapping;
+    let bins: Vec<Bin> = sketch.positive_value_store.get_ascending_iter().collect();
+    assert_eq!(
+        vec![
+            Bin {
+                index: index_mapping.index(1.0),
+                count: 2.0
+            },
+            Bin {
+                index: index_mapping.index(100.0),
+                count: 3.0
+            }
+        ],
+        bins
+    );
+    let mut total = 0.0;
+    for bin in sketch.positive_value_store.get_descending_iter() {
+        assert!(bin.index >= index_mapping.index(1.0));
+        total += bin.count;
+    }
+    assert_eq!(5.0, total);
+}
+
+#[test]
+fn test_sketch_merge_with_store_types() {
+    let unbounded = || DDSketch::unbounded_dense(2e-2).unwrap();
+    let collapsing = || DDSketch::collapsing_lowest_dense(2e-2, 50).unwrap();
+    let fill = |mut sketch: DDSketch, from: i32, to: i32| {
+        for i in from..=to {
+            sketch.accept(i as f64);
+            sketch.accept(-(i as f64));
+        }
+        sketch
+    };
+    let expected_bins = fill(unbounded(), 1, 10000).num_bins();
+
+    for (self_is_collapsing, other_is_collapsing) in
+        [(false, false), (false, true), (true, false), (true, true)]
+    {
+        let make = |is_collapsing: bool| {
+            if is_collapsing {
+                collapsing()
+            } else {
+                unbounded()
+            }
+        };
+        let mut sketch = fill(make(self_is_collapsing), 1, 100);
+        let other = fill(make(other_is_collapsing), 50, 10000);
+        sketch.merge_with(&other).unwrap();
+
+        assert_eq!(200.0 + other.get_count(), sketch.get_count());
+        for store in [&sketch.negative_value_store, &sketch.positive_value_store] {
+            if self_is_collapsing {
+                assert!(store.get_max_index() - store.get_min_index() < 50);
+                assert!(store.capacity_bins() <= 50);
+            } else {
+                assert!(store.get_max_index() - store.get_min_index() >= 50);
+            }
+        }
+        if !self_is_collapsing && !other_is_collapsing {
+            assert_eq!(expected_bins, sketch.num_bins());
+        }
+        assert_eq!(other.get_max(), sketch.get_max());
+    }
+
+    assert!(unbounded()
+        .merge_with(&DDSketch::unbounded_dense(1e-2).unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_sketch_percentile_table() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert!(sketch.percentile_table(0.1).unwrap().is_empty());
+    for i in -500..=1000 {
+        sketch.accept((i as f64).powi(3));
+    }
+
+    let table = sketch.percentile_table(0.1).unwrap();
+    assert_eq!(11, table.len());
+    assert_eq!((0.0, sketch.get_min().unwrap()), table[0]);
+    assert_eq!((1.0, sketch.get_max().unwrap()), table[10]);
+    assert!(table.windows(2).all(|rows| rows[0].1 <= rows[1].1));
+    for (q, value) in &table {
+        assert_eq!(sketch.get_value_at_quantile(*q), Some(*value));
+    }
+
+    let table = sketch.percentile_table(0.001).unwrap();
+    assert_eq!(1001, table.len());
+    assert!(table.windows(2).all(|rows| rows[0].1 <= rows[1].1));
+    assert_eq!(2, sketch.percentile_table(1.0).unwrap().len());
+    assert_eq!(2, sketch.percentile_table(0.7).unwrap().len());
+    for step in [0.0, -0.1, 1.5, f64::NAN] {
+        assert!(sketch.percentile_table(step).is_err());
+    }
+}
+
+#[test]
+fn test_sketch_exact_min_max() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(None, sketch.get_exact_min());
+    assert_eq!(None, sketch.get_exact_max());
+    for value in [3.3, -7.7, 1234.5, 0.0, 42.0] {
+        sketch.accept(value);
+    }
+    assert_eq!(Some(1234.5), sketch.get_exact_max());
+    assert_eq!(Some(-7.7), sketch.get_exact_min());
+    assert_ne!(sketch.get_exact_max(), sketch.get_max());
+    assert_ne!(sketch.get_exact_min(), sketch.get_min());
+
+    let mut other = sketch.fresh();
+    other.accept(5000.25);
+    sketch.merge_with(&other).unwrap();
+    assert_eq!(Some(5000.25), sketch.get_exact_max());
+
+    let bytes = sketch.encode().unwrap();
+    let decoded = DDSketch::decode(&bytes).unwrap();
+    assert_eq!(Some(-7.7), decoded.get_exact_min());
+    assert_eq!(Some(5000.25), decoded.get_exact_max());
+    let summary = DDSketch::decode_summary_only(&bytes).unwrap();
+    assert_eq!(Some(-7.7), summary.min);
+    assert_eq!(Some(5000.25), summary.max);
+    assert!(bytes.len() <= sketch.max_serialized_size());
+
+    let mut merged = DDSketch::unbounded_dense(2e-2).unwrap();
+    merged.accept(-100.5);
+    merged.decode_and_merge_with(&bytes).unwrap();
+    assert_eq!(Some(-100.5), merged.get_exact_min());
+    assert_eq!(Some(5000.25), merged.get_exact_max());
+
+    sketch.clear();
+    assert_eq!(None, sketch.get_exact_max());
+}
+
+#[test]
+fn test_sketch_exact_min_max_unknown() {
+    let mut sketch = DDSketch::unbounded_dense(1e-2).unwrap();
+    sketch.accept(1.0);
+    let mut store = UnboundedSizeDenseStore::new();
+    store.add(sketch.index_of(1000.0).unwrap(), 1.0);
+    let (mapping, _, negative_value_store, _) = sketch.fresh().into_parts();
+    let other = DDSketch::from_parts(mapping, Box::new(store), negative_value_store, 0.0).unwrap();
+    assert_eq!(None, other.get_exact_max());
+
+    let bytes = other.encode().unwrap();
+    let mut decoded = sketch.fresh();
+    decoded.accept(1.0);
+    decoded.decode_and_merge_with(&bytes).unwrap();
+    assert_eq!(None, decoded.get_exact_max());
+
+    sketch.merge_with(&other).unwrap();
+    assert_eq!(None, sketch.get_exact_min());
+    assert_eq!(None, sketch.get_exact_max());
+    assert!(sketch.get_max().unwrap() > 990.0);
+    // Accepting more values does not make the extremes known again.
+    sketch.accept(2000.0);
+    assert_eq!(None, sketch.get_exact_max());
+    let restored = DDSketch::decode(&sketch.encode().unwrap()).unwrap();
+    assert_eq!(None, restored.get_exact_max());
+
+    // Merging an empty sketch without extremes keeps them known.
+    let mut known = sketch.fresh();
+    known.accept(3.0);
+    known.merge_with(&sketch.fresh()).unwrap();
+    assert_eq!(Some(3.0), known.get_exact_max());
+
+    sketch.clear();
+    sketch.accept(4.0);
+    assert_eq!(Some(4.0), sketch.get_exact_max());
+}
+
+#[test]
+fn test_sketch_to_json_from_json() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(0.01, 2048).unwrap();
+    for i in -500..1000 {
+        sketch.accept(i as f64 * 0.37);
+    }
+    sketch.accept_with_count(0.0, 2.5);
+
+    let json = sketch.to_json();
+    assert!(json.starts_with("{\"mapping\":{\"layout\":\"LogCubic\""));
+    assert!(json.contains("\"zero_count\":3.5"));
+    let restored = DDSketch::from_json(&json).unwrap();
+    assert_eq!(json, restored.to_json());
+    assert_eq!(sketch.get_count(), restored.get_count());
+    for q in [0.0, 0.1, 0.5, 0.9, 1.0] {
+        assert_eq!(
+            sketch.get_value_at_quantile(q),
+            restored.get_value_at_quantile(q)
+        );
+    }
+
+    let empty = DDSketch::unbounded_dense(0.02).unwrap();
+    let restored = DDSketch::from_json(&empty.to_json()).unwrap();
+    assert!(restored.is_empty());
+    assert_eq!(empty.to_json(), restored.to_json());
+
+    assert!(DDSketch::from_json("").is_err());
+    assert!(DDSketch::from_json(&json[..json.len() - 1]).is_err());
+    assert!(DDSketch::from_json(&json.replace("LogCubic", "Linear")).is_err());
+    assert!(DDSketch::from_json(&json.replace("\"count\":1.0", "\"count\":-1.0")).is_err());
+    let far_apart = empty.to_json().replace(
+        "\"positive\":[]",
+        "\"positive\":[{\"index\":-2000000000,\"count\":1.0},{\"index\":2000000000,\"count\":1.0}]",
+    );
+    assert!(matches!(
+        DDSketch::from_json(&far_apart),
+        Err(Error::InvalidArgument(_))
+    ));
+
+    let deep = format!("{{\"mapping\":{}", "[".repeat(1_000_000));
+    assert!(matches!(
+        DDSketch::from_json(&deep),
+        Err(Error::InvalidArgument(_))
+    ));
+
+    let mut infinite = DDSketch::unbounded_dense(0.02).unwrap();
+    infinite.accept_with_count(0.0, f64::INFINITY);
+    let json = infinite.to_json();
+    assert!(json.contains("\"zero_count\":null"));
+    assert!(DDSketch::from_json(&json).is_err());
+}
+
+#[test]
+fn test_sketch_max_indexable_value() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    let max = sketch.max_indexable_value();
+    assert!(max.is_finite());
+    assert!((max - f64::MAX / 1.01).abs() <= max * 1e-12);
+
+    sketch.accept(max);
+    sketch.accept(-max);
+    assert_eq!(2.0, sketch.get_count());
+    assert!(sketch.get_max().unwrap().is_finite());
+
+    let above = max * (1.0 + 1e-9);
+    sketch.accept(above);
+    sketch.accept(f64::INFINITY);
+    assert_eq!(2.0, sketch.get_count());
+    assert!(sketch.try_accept(above).is_err());
+    assert!(sketch.try_accept(-above).is_err());
+    assert!(sketch.try_accept(f64::NAN).is_err());
+    assert!(sketch.try_accept(1.0).is_ok());
+    assert_eq!(3.0, sketch.get_count());
+
+    sketch.set_accept_bounds(-10.0, 10.0).unwrap();
+    assert!(sketch.try_accept(100.0).is_err());
+    assert!(sketch.try_accept(-100.0).is_err());
+    assert!(sketch.try_accept(10.0).is_ok());
+    assert_eq!(4.0, sketch.get_count());
+}
+
+#[test]
+fn test_sketch_from_values_auto() {
+    let clustered: Vec<f64> = (0..10000).map(|i| 100.0 + (i % 500) as f64 * 0.1).collect();
+    let scattered: Vec<f64> = (0..200)
+        .map(|i| if i % 2 == 0 { 1e-6 } else { 1e6 } * (1.0 + i as f64))
+        .collect();
+
+    for (values, dense) in [(&clustered, true), (&scattered, false)] {
+        let sketch = DDSketch::from_values_auto(0.01, values).unwrap();
+        assert_eq!(
+            dense,
+            sketch
+                .positive_value_store
+                .as_any()
+                .is::<UnboundedSizeDenseStore>()
+        );
+
+        let mut expected = DDSketch::unbounded_dense(0.01).unwrap();
+        expected.extend(values.iter().copied());
+        assert_eq!(expected.get_count(), sketch.get_count());
+        for q in [0.0, 0.01, 0.25, 0.5, 0.75, 0.99, 1.0] {
+            assert_eq!(
+                expected.get_value_at_quantile(q),
+                sketch.get_value_at_quantile(q)
+            );
+        }
+    }
+
+    assert!(DDSketch::from_values_auto(0.01, &[]).unwrap().is_empty());
+    assert!(DDSketch::from_values_auto(2.0, &[1.0]).is_err());
+}
+
+#[test]
+fn test_sketch_encode_with_mode() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    for i in -100..1000 {
+        sketch.accept(i as f64 * 1.5);
+    }
+    let mut unit_counts = DDSketch::unbounded_dense(0.01).unwrap();
+    for i in 0..100 {
+        unit_counts.accept(1.1f64.powi(i));
+    }
+
+    for sketch in [&sketch, &unit_counts] {
+        for mode in [
+            BinEncodingMode::IndexDeltasAndCounts,
+            BinEncodingMode::IndexDeltas,
+            BinEncodingMode::ContiguousCounts,
+        ] {
+            let bytes = sketch.encode_with_mode(mode).unwrap();
+            let decoded = DDSketch::decode(&bytes).unwrap();
+            assert_eq!(sketch.get_count(), decoded.get_count());
+            for q in [0.0, 0.1, 0.5, 0.9, 1.0] {
+                assert_eq!(
+                    sketch.get_value_at_quantile(q),
+                    decoded.get_value_at_quantile(q)
+                );
+            }
+        }
+        let default_size = sketch.encode().unwrap().len();
+        for mode in [
+            BinEncodingMode::IndexDeltasAndCounts,
+            BinEncodingMode::ContiguousCounts,
+        ] {
+            assert!(default_size <= sketch.encode_with_mode(mode).unwrap().len());
+        }
+    }
+    assert!(
+        unit_counts
+            .encode_with_mode(BinEncodingMode::IndexDeltas)
+            .unwrap()
+            .len()
+            < unit_counts
+                .encode_with_mode(BinEncodingMode::IndexDeltasAndCounts)
+                .unwrap()
+                .len()
+    );
+}
+
+#[test]
+fn test_sketch_get_count_u64() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    assert_eq!(0, sketch.get_count_u64());
+    assert_eq!(Some(0), sketch.get_count_at_quantile_u64(0.5));
+
+    sketch.accept_with_count(1.0, 0.4);
+    assert_eq!(0, sketch.get_count_u64());
+    sketch.accept_with_count(2.0, 2.3);
+    assert_eq!(3, sketch.get_count_u64());
+    sketch.accept_with_count(-3.0, 0.8);
+    assert_eq!(4, sketch.get_count_u64());
+
+    assert_eq!(Some(0), sketch.get_count_at_quantile_u64(0.0));
+    assert_eq!(Some(2), sketch.get_count_at_quantile_u64(0.5));
+    assert_eq!(Some(4), sketch.get_count_at_quantile_u64(1.0));
+    assert_eq!(None, sketch.get_count_at_quantile_u64(1.5));
+    assert_eq!(None, sketch.get_count_at_quantile_u64(f64::NAN));
+
+    sketch.accept_with_count(4.0, 1e30);
+    assert_eq!(u64::MAX, sketch.get_count_u64());
+}
+
+#[test]
+fn test_sketch_get_quantile_bounds() {
+    let mut values: Vec<f64> = (-300..700).map(|i| i as f64 * 0.73).collect();
+    values.extend([0.01, -0.02, 0.3, 1e6, -1e-6]);
+    let mut sketch = DDSketch::unbounded_dense(0.02)
+        .unwrap()
+        .with_zero_threshold(0.5);
+    sketch.extend(values.iter().copied());
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    for i in 0..=100 {
+        let q = i as f64 / 100.0;
+        let (lower, upper) = sketch.get_quantile_bounds(q).unwrap();
+        let value = sketch.get_value_at_quantile(q).unwrap();
+        let expected = values[(q * (values.len() - 1) as f64) as usize];
+        assert!(lower <= value && value <= upper);
+        assert!(lower <= expected && expected <= upper);
+    }
+    assert_eq!(None, sketch.get_quantile_bounds(-0.1));
+    assert_eq!(
+        None,
+        DDSketch::unbounded_dense(0.02)
+            .unwrap()
+            .get_quantile_bounds(0.5)
+    );
+}
+
+#[test]
+fn test_sketch_decode_and_merge_downsampled() {
+    let mut fine = DDSketch::unbounded_dense(0.001).unwrap();
+    let mut coarse = DDSketch::unbounded_dense(0.02).unwrap();
+    let mut expected = DDSketch::unbounded_dense(0.02).unwrap();
+    for i in -200..800 {
+        let value = i as f64 * 0.37;
+        fine.accept(value);
+        expected.accept(value);
+        coarse.accept(value + 1000.0);
+        expected.accept(value + 1000.0);
+    }
+
+    coarse
+        .decode_and_merge_downsampled(&fine.encode().unwrap())
+        .unwrap();
+    assert_eq!(expected.get_count(), coarse.get_count());
+    assert_eq!(expected.get_min(), coarse.get_min());
+    assert_eq!(expected.get_max(), coarse.get_max());
+    for q in [0.0, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+        let expected_value = expected.get_value_at_quantile(q).unwrap();
+        let value = coarse.get_value_at_quantile(q).unwrap();
+        assert!((expected_value - value).abs() <= expected_value.abs() * 0.045);
+    }
+
+    let same = coarse.encode().unwrap();
+    coarse.decode_and_merge_downsampled(&same).unwrap();
+    assert_eq!(2.0 * expected.get_count(), coarse.get_count());
+
+    let mut finer_target = DDSketch::unbounded_dense(0.001).unwrap();
+    assert!(finer_target
+        .decode_and_merge_downsampled(&expected.encode().unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_sketch_collapsing_stores_near_index_limits() {
+    // The last mapping indexes values across almost the whole i32 range.
+    for (gamma, index_offset) in [
+        (1.02, i32::MAX as f64 - 5.0),
+        (1.02, i32::MIN as f64 + 5.0),
+        (1.0 + 1e-8, 0.0),
+    ] {
+        let index_mapping =
+            IndexMapping::with_gamma_offset(IndexMappingLayout::LOG, gamma, index_offset).unwrap();
+        for keeps_highest in [true, false] {
+            let new_store = || -> Box<dyn Store> {
+                if keeps_highest {
+                    Box::new(CollapsingLowestDenseStore::with_capacity(8).unwrap())
+                } else {
+                    Box::new(CollapsingHighestDenseStore::with_capacity(8).unwrap())
+                }
+            };
+            let mut sketch =
+                DDSketch::from_parts(index_mapping.clone(), new_store(), new_store(), 0.0).unwrap();
+            let min = sketch.min_indexed_value();
+            let max = sketch.max_indexable_value();
+            let mut values = vec![min, max, -min, -max];
+            let mut value = min;
+            while value < max {
+                values.push(value);
+                values.push(-value);
+                value *= 1.7;
+            }
+            for value in values.iter() {
+                sketch.accept(*value);
+            }
+            values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            assert_eq!(values.len() as f64, sketch.get_count());
+            assert_eq!(Some(-max), sketch.get_exact_min());
+            assert_eq!(Some(max), sketch.get_exact_max());
+            // Only the bins at the end of the range kept by the stores are exact.
+            let ranks = if keeps_highest {
+                [0, values.len() - 1]
+            } else {
+                [values.len() / 2 - 1, values.len() / 2]
+            };
+            for rank in ranks {
+                let expected = values[rank];
+                let value = sketch.get_value_at_rank(rank as f64).unwrap();
+                assert!((value - expected).abs() <= expected.abs() * 0.01);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sketch_zero_bucket_fraction() {
+    let mut sketch = DDSketch::unbounded_dense(0.01)
+        .unwrap()
+        .with_zero_threshold(1e-3);
+    assert_eq!(None, sketch.zero_bucket_fraction());
+
+    sketch.extend([0.0, 1e-4, -1e-5, 1.0, -2.0, 3.0, 5e-3, 1e-3]);
+    assert_eq!(Some(3.0 / 8.0), sketch.zero_bucket_fraction());
+    sketch.accept_with_count(0.0, 2.0);
+    assert_eq!(Some(0.5), sketch.zero_bucket_fraction());
+}
+
+#[test]
+fn test_sketch_accept_many() {
+    let values: Vec<f64> = (-500..1500).map(|i| i as f64 * 0.21).collect();
+    let counts: Vec<f64> = (0..values.len()).map(|i| (i % 7) as f64 * 0.5).collect();
+
+    let mut expected = DDSketch::unbounded_dense(0.01).unwrap();
+    let mut expected_with_counts = DDSketch::unbounded_dense(0.01).unwrap();
+    for (value, count) in values.iter().zip(&counts) {
+        expected.accept(*value);
+        expected_with_counts.accept_with_count(*value, *count);
+    }
+
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    sketch.accept_many(&values);
+    let mut sketch_with_counts = DDSketch::unbounded_dense(0.01).unwrap();
+    sketch_with_counts
+        .accept_many_with_counts(&values, &counts)
+        .unwrap();
+
+    assert!(expected.approx_eq(&sketch, 0.0));
+    assert!(expected_with_counts.approx_eq(&sketch_with_counts, 0.0));
+    assert_eq!(expected.get_sum(), sketch.get_sum());
+    assert_eq!(expected_with_counts.get_sum(), sketch_with_counts.get_sum());
+    assert!(sketch
+        .accept_many_with_counts(&values, &counts[1..])
+        .is_err());
+    assert_eq!(expected.get_count(), sketch.get_count());
+}
+
+#[test]
+fn test_sketch_encode_is_deterministic() {
+    let values: Vec<f64> = (0..2000).map(|i| (i as f64 - 700.0) * 1.3).collect();
+    let orders: Vec<Vec<f64>> = vec![
+        values.clone(),
+        values.iter().rev().copied().collect(),
+        (0..values.len())
+            .map(|i| values[i * 7919 % values.len()])
+            .collect(),
+    ];
+
+    let constructors: [fn() -> DDSketch; 3] = [
+        || DDSketch::unbounded_dense(0.01).unwrap(),
+        || DDSketch::adaptive(0.01).unwrap(),
+        || DDSketch::collapsing_lowest_dense(0.01, 256).unwrap(),
+    ];
+    for new_sketch in constructors {
+        let encoded: Vec<Vec<u8>> = orders
+            .iter()
+            .map(|order| {
+                let mut sketch = new_sketch();
+                sketch.extend(order.iter().copied());
+                sketch.encode().unwrap()
+            })
+            .collect();
+        assert_eq!(encoded[0], encoded[1]);
+        assert_eq!(encoded[0], encoded[2]);
+    }
+}
+
+#[test]
+fn test_sketch_clear_and_shrink() {
+    let constructors: [fn() -> DDSketch; 4] = [
+        || DDSketch::unbounded_dense(0.01).unwrap(),
+        || DDSketch::unbounded_dense_u64(0.01).unwrap(),
+        || DDSketch::collapsing_lowest_dense(0.01, 4096).unwrap(),
+        || DDSketch::adaptive(0.01).unwrap(),
+    ];
+    for new_sketch in constructors {
+        let mut sketch = new_sketch();
+        let empty_memory_usage = sketch.memory_usage();
+        for i in 1..5000 {
+            sketch.accept(i as f64 * 1.01);
+            sketch.accept(-1.0 / i as f64);
+        }
+        let memory_usage = sketch.memory_usage();
+        assert!(memory_usage > empty_memory_usage);
+
+        sketch.clear();
+        assert!(sketch.is_empty());
+        assert!(sketch.memory_usage() <= memory_usage);
+
+        sketch.clear_and_shrink();
+        assert!(sketch.is_empty());
+        assert_eq!(empty_memory_usage, sketch.memory_usage());
+
+        sketch.accept(2.0);
+        assert_eq!(1.0, sketch.get_count());
+        assert!(sketch.memory_usage() < memory_usage);
+    }
+}
+
+#[test]
+fn test_sketch_negative_zero_and_denormals() {
+    let mut zero = DDSketch::unbounded_dense(0.01).unwrap();
+    zero.accept(0.0);
+    let mut negative_zero = DDSketch::unbounded_dense(0.01).unwrap();
+    negative_zero.accept(-0.0);
+    assert_eq!(1.0, negative_zero.zero_count());
+    assert!(negative_zero.get_exact_min().unwrap().is_sign_positive());
+    assert!(negative_zero.get_exact_max().unwrap().is_sign_positive());
+    assert_eq!(zero.encode().unwrap(), negative_zero.encode().unwrap());
+
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    let min_indexed_value = sketch.min_indexed_value();
+    assert!(min_indexed_value > f64::MIN_POSITIVE);
+    let below = f64::from_bits(min_indexed_value.to_bits() - 1);
+    for value in [
+        f64::MIN_POSITIVE,
+        -f64::MIN_POSITIVE,
+        f64::MIN_POSITIVE / 4.0,
+        -5e-324,
+        below,
+        -below,
+    ] {
+        sketch.accept(value);
+    }
+    assert_eq!(6.0, sketch.zero_count());
+    assert!(sketch.positive_value_store.is_empty());
+    assert!(sketch.negative_value_store.is_empty());
+
+    let above = f64::from_bits(min_indexed_value.to_bits() + 1);
+    for value in [min_indexed_value, above, -min_indexed_value, -above] {
+        sketch.accept(value);
+    }
+    assert_eq!(6.0, sketch.zero_count());
+    for store in [&sketch.positive_value_store, &sketch.negative_value_store] {
+        assert_eq!(2.0, store.get_total_count());
+        let index = store.get_min_index();
+        let value = sketch.index_mapping.value(index);
+        assert_eq!(index, sketch.index_mapping.index(value));
+        assert!((value - min_indexed_value).abs() <= min_indexed_value * 0.011);
+    }
+}
+
+#[test]
+fn test_sketch_merge_with_report() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(0.01, 200).unwrap();
+    let mut other = DDSketch::collapsing_lowest_dense(0.01, 200).unwrap();
+    for i in 1..=100 {
+        sketch.accept(i as f64);
+        other.accept(i as f64 * 100.0);
+    }
+    let count = sketch.get_count() + other.get_count();
+
+    let report = sketch.merge_with_report(&other).unwrap();
+    assert!(report.collapsed_count > 0.0);
+    assert_eq!(0.0, report.dropped_out_of_range);
+    assert_eq!(count, sketch.get_count());
+    let low = sketch.positive_value_store.collapsed_low_index().unwrap();
+    assert!(sketch.positive_value_store.get_count_in_range(low, low) >= report.collapsed_count);
+
+    let mut unbounded = DDSketch::unbounded_dense(0.01).unwrap();
+    unbounded.extend((1..=100).map(|i| i as f64 * 1e4));
+    let mut empty = DDSketch::unbounded_dense(0.01).unwrap();
+    assert_eq!(
+        MergeReport::default(),
+        empty.merge_with_report(&unbounded).unwrap()
+    );
+    assert!(empty
+        .merge_with_report(&DDSketch::unbounded_dense(0.02).unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_sketch_presets() {
+    for (preset, relative_accuracy) in [
+        (Preset::Fine, 0.001),
+        (Preset::Standard, 0.01),
+        (Preset::Coarse, 0.02),
+    ] {
+        assert_eq!(relative_accuracy, preset.relative_accuracy());
+        let mut sketch = DDSketch::unbounded_dense_preset(preset);
+        assert!((sketch.index_mapping.relative_accuracy() - relative_accuracy).abs() <= 1e-12);
+        sketch.accept(10.0);
+        let value = sketch.get_value_at_quantile(0.5).unwrap();
+        assert!((value - 10.0).abs() <= 10.0 * relative_accuracy);
+    }
+}
+
+#[test]
+fn test_sketch_otel_exponential_histogram() {
+    let mut values: Vec<f64> = (1..3000).map(|i| (i as f64 * 0.37).powf(1.3)).collect();
+    values.extend((1..1000).map(|i| -(i as f64) * 0.01));
+    values.extend([0.0; 10]);
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    sketch.extend(values.iter().copied());
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let histogram = sketch.to_otel_exponential_histogram();
+    let base = 2f64.powf(2f64.powi(-histogram.scale));
+    assert!(base <= sketch.index_mapping.gamma());
+    assert_eq!(values.len() as u64, histogram.count);
+    assert_eq!(10, histogram.zero_count);
+    assert_eq!(sketch.get_exact_min(), histogram.min);
+    assert_eq!(sketch.get_exact_max(), histogram.max);
+
+    let restored = DDSketch::from_otel(&histogram).unwrap();
+    assert_eq!(sketch.get_count(), restored.get_count());
+    assert_eq!(sketch.zero_count(), restored.zero_count());
+    assert_eq!(sketch.get_exact_min(), restored.get_exact_min());
+    assert_eq!(sketch.get_exact_max(), restored.get_exact_max());
+    let restored_histogram = restored.to_otel_exponential_histogram();
+    assert_eq!(histogram.scale, restored_histogram.scale);
+    assert_eq!(histogram.positive, restored_histogram.positive);
+    assert_eq!(histogram.negative, restored_histogram.negative);
+
+    // The errors of both conversions add up.
+    let accuracy = sketch.index_mapping.relative_accuracy() + (base - 1.0) / (base + 1.0);
+    for i in 0..=100 {
+        let q = i as f64 / 100.0;
+        let expected = values[(q * (values.len() - 1) as f64) as usize];
+        let value = restored.get_value_at_quantile(q).unwrap();
+        assert!((value - expected).abs() <= expected.abs() * accuracy * 1.01);
+    }
+
+    let mut invalid = histogram.clone();
+    invalid.scale = 21;
+    assert!(DDSketch::from_otel(&invalid).is_err());
+    invalid.scale = 0;
+    invalid.positive.offset = i32::MAX;
+    assert!(DDSketch::from_otel(&invalid).is_err());
+}
+
+#[test]
+fn test_sketch_accept_unchecked() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    let mut unchecked = DDSketch::unbounded_dense(0.01).unwrap();
+    for i in -500i32..1500 {
+        let value = i as f64 * 0.37;
+        let count = (i.rem_euclid(3) + 1) as f64;
+        sketch.accept_with_count(value, count);
+        unchecked.accept_unchecked(value, count);
+    }
+    // A zero count does not move the extremes.
+    sketch.accept_with_count(1e9, 0.0);
+    unchecked.accept_unchecked(1e9, 0.0);
+
+    assert_eq!(sketch.get_count(), unchecked.get_count());
+    assert_eq!(sketch.zero_count(), unchecked.zero_count());
+    assert_eq!(sketch.get_sum(), unchecked.get_sum());
+    assert_eq!(sketch.get_exact_min(), unchecked.get_exact_min());
+    assert_eq!(sketch.get_exact_max(), unchecked.get_exact_max());
+    assert!(unchecked.get_exact_max().unwrap() < 1e9);
+    for q in [0.0, 0.01, 0.25, 0.5, 0.75, 0.99, 1.0] {
+        assert_eq!(
+            sketch.get_value_at_quantile(q),
+            unchecked.get_value_at_quantile(q)
+        );
+    }
+}
+
+#[test]
+fn test_sketch_accept_unchecked_skips_the_checks() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    sketch.set_accept_bounds(-10.0, 10.0).unwrap();
+    sketch.accept_with_count(100.0, 1.0);
+    sketch.accept_with_count(1.0, -1.0);
+    assert!(sketch.is_empty());
+
+    // The bounds are left to the debug assertions, release builds record the value as is.
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        sketch.accept_unchecked(100.0, 1.0);
+    }));
+    if cfg!(debug_assertions) {
+        assert!(result.is_err());
+    } else {
+        assert_eq!(1.0, sketch.get_count());
+        assert_eq!(Some(100.0), sketch.get_exact_max());
+    }
+}
+
+#[test]
+fn test_sketch_query_combined_quantile() {
+    let mut sketches = Vec::new();
+    for tenant in 0..4 {
+        let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+        for i in 0..(200 * (tenant + 1)) {
+            sketch.accept((i as f64 - 150.0) * (tenant as f64 + 0.5));
+        }
+        sketches.push(sketch);
+    }
+    sketches.push(DDSketch::unbounded_dense(0.01).unwrap());
+
+    let mut merged = DDSketch::unbounded_dense(0.01).unwrap();
+    for sketch in &sketches {
+        merged.merge_with(sketch).unwrap();
+    }
+    let subset: Vec<&DDSketch> = sketches.iter().collect();
+    for i in 0..=100 {
+        let q = i as f64 / 100.0;
+        assert_eq!(
+            merged.get_value_at_quantile(q),
+            query_combined_quantile(&subset, q).unwrap()
+        );
+    }
+    assert_eq!(None, query_combined_quantile(&subset, 1.5).unwrap());
+    assert_eq!(None, query_combined_quantile(&[], 0.5).unwrap());
+    assert_eq!(
+        sketches[1].get_value_at_quantile(0.3),
+        query_combined_quantile(&[&sketches[1]], 0.3).unwrap()
+    );
+
+    let other = DDSketch::unbounded_dense(0.02).unwrap();
+    assert!(query_combined_quantile(&[&sketches[0], &other], 0.5).is_err());
+}
+
+#[test]
+fn test_sketch_index_of() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    let positive = sketch.index_of(12.5).unwrap();
+    let negative = sketch.index_of(-12.5).unwrap();
+    assert_eq!(positive, negative);
+    assert_eq!(sketch.index_mapping.index(12.5), positive);
+    assert!((sketch.value_of_index(positive, false) - 12.5).abs() <= 12.5 * 0.01);
+    assert!((sketch.value_of_index(negative, true) + 12.5).abs() <= 12.5 * 0.01);
+
+    sketch.accept(12.5);
+    sketch.accept(-12.5);
+    assert_eq!(
+        1.0,
+        sketch
+            .positive_value_store
+            .get_count_in_range(positive, positive)
+    );
+    assert_eq!(
+        1.0,
+        sketch
+            .negative_value_store
+            .get_count_in_range(negative, negative)
+    );
+
+    assert_eq!(None, sketch.index_of(0.0));
+    assert_eq!(None, sketch.index_of(sketch.min_indexed_value / 2.0));
+    assert_eq!(None, sketch.index_of(-sketch.min_indexed_value / 2.0));
+    assert_eq!(None, sketch.index_of(f64::INFINITY));
+    assert_eq!(None, sketch.index_of(f64::NAN));
+
+    sketch.set_accept_bounds(-10.0, 10.0).unwrap();
+    assert_eq!(None, sketch.index_of(12.5));
+    assert_eq!(None, sketch.index_of(-12.5));
+    assert_eq!(Some(sketch.index_mapping.index(5.0)), sketch.index_of(5.0));
+}
+
+#[test]
+fn test_sketch_decode_limited() {
+    let limits = DecodeLimits {
+        max_bins: 4096,
+        max_bytes: 1 << 16,
+    };
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    for i in -100..1000 {
+        sketch.accept(i as f64 * 0.5);
+    }
+    let bytes = sketch.encode().unwrap();
+    let decoded = DDSketch::decode_limited(&bytes, limits).unwrap();
+    assert_eq!(sketch.get_count(), decoded.get_count());
+    assert_eq!(
+        sketch.get_value_at_quantile(0.5),
+        decoded.get_value_at_quantile(0.5)
+    );
+    assert!(DDSketch::decode_limited(
+        &bytes,
+        DecodeLimits {
+            max_bins: 4096,
+            max_bytes: bytes.len() - 1,
+        }
+    )
+    .is_err());
+
+    // A positive store of two contiguous bins whose index delta spans the whole i32 range.
+    let mut output = Output::with_capacity(64);
+    // The flag of a positive store encoded with contiguous counts, as a single-byte var-long.
+    sketches_rust::serde::encode_unsigned_var_long(&mut output, 13).unwrap();
+    sketches_rust::serde::encode_unsigned_var_long(&mut output, 2).unwrap();
+    sketches_rust::serde::encode_signed_var_long(&mut output, i32::MIN as i64).unwrap();
+    sketches_rust::serde::encode_signed_var_long(&mut output, u32::MAX as i64).unwrap();
+    sketches_rust::serde::encode_var_double(&mut output, 1.0).unwrap();
+    sketches_rust::serde::encode_var_double(&mut output, 1.0).unwrap();
+    let mut malicious = DDSketch::unbounded_dense(0.01).unwrap().encode().unwrap();
+    malicious.extend(output.trim());
+    assert!(matches!(
+        DDSketch::decode_limited(&malicious, limits),
+        Err(Error::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_sketch_merge_with_cap() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    let mut other = DDSketch::unbounded_dense(0.01).unwrap();
+    let mut expected = DDSketch::unbounded_dense(0.01).unwrap();
+    for i in -200..800 {
+        sketch.accept(i as f64);
+        expected.accept(i as f64);
+    }
+    other.accept(0.0);
+    for i in 0..1000 {
+        other.accept(i as f64 * 0.5);
+    }
+    expected.merge_with(&other).unwrap();
+
+    sketch.merge_with_cap(&other, 500.0).unwrap();
+    assert!((sketch.get_count() - 500.0).abs() < 1e-9);
+    for q in [0.0, 0.01, 0.25, 0.5, 0.75, 0.99, 1.0] {
+        assert_eq!(
+            expected.get_value_at_quantile(q),
+            sketch.get_value_at_quantile(q)
+        );
+    }
+    assert_eq!(expected.get_exact_min(), sketch.get_exact_min());
+    assert_eq!(expected.get_exact_max(), sketch.get_exact_max());
+
+    // A count below the target is left untouched.
+    sketch
+        .merge_with_cap(&DDSketch::unbounded_dense(0.01).unwrap(), 1e6)
+        .unwrap();
+    assert!((sketch.get_count() - 500.0).abs() < 1e-9);
+    assert!(sketch.merge_with_cap(&other, 0.0).is_err());
+
+    let mut sketch = DDSketch::unbounded_dense_u64(0.01).unwrap();
+    let mut other = DDSketch::unbounded_dense_u64(0.01).unwrap();
+    for i in 0..10 {
+        sketch.accept(i as f64 + 1.0);
+        other.accept(i as f64 * 10.0 + 1.0);
+    }
+    sketch.merge_with_cap(&other, 7.0).unwrap();
+    assert_eq!(7.0, sketch.get_count());
+    assert!(sketch.get_value_at_quantile(0.0).unwrap() < 5.0);
+    assert!(sketch.get_value_at_quantile(1.0).unwrap() > 50.0);
+    assert!(sketch.merge_with_cap(&other, f64::NAN).is_err());
+}
+
+#[test]
+fn test_sketch_decode_sketches_go_encoding() {
+    // A DDSketchWithExactSummaryStatistics of sketches-go with a relative accuracy of 2%, holding
+    // [-1.1, -1.0, 0.0, 1.0, 1.05, 1.1, 1.2, 1.25]. Unlike this crate, sketches-go writes the
+    // summary statistics and the zero count before the index mapping.
+    let bytes = [
+        160, 134, 32, 132, 0, 0, 0, 0, 0, 0, 12, 64, 136, 154, 153, 153, 153, 153, 153, 241, 191,
+        140, 0, 0, 0, 0, 0, 0, 244, 63, 4, 2, 2, 42, 120, 57, 5, 47, 167, 240, 63, 0, 0, 0, 0, 0,
+        0, 0, 0, 13, 6, 0, 2, 2, 2, 2, 0, 2, 2, 15, 3, 0, 2, 2, 0, 2,
+    ];
+    let sketch = DDSketch::decode(&bytes).unwrap();
+    assert_eq!(IndexMappingLayout::LOG, sketch.index_mapping.layout());
+    assert_eq!(0.0, sketch.index_mapping.index_offset());
+    assert_eq!(8.0, sketch.get_count());
+    assert_eq!(1.0, sketch.zero_count());
+    assert_eq!(Some(-1.1), sketch.get_exact_min());
+    assert_eq!(Some(1.25), sketch.get_exact_max());
+    let p50 = sketch.get_value_at_quantile(0.5).unwrap();
+    assert!((p50 - 1.0).abs() <= 0.02 + 1e-12);
+}
+
+#[test]
+fn test_sketch_emd_distance() {
+    let shifted = |shift: f64| {
+        let mut sketch = DDSketch::unbounded_dense(0.001).unwrap();
+        for i in 0..1000 {
+            sketch.accept(1000.0 + i as f64 + shift);
+        }
+        sketch
+    };
+    let sketch = shifted(0.0);
+    assert_eq!(0.0, sketch.emd_distance(&shifted(0.0)).unwrap());
+
+    let distance = sketch.emd_distance(&shifted(50.0)).unwrap();
+    assert!((distance - 50.0).abs() <= 50.0 * 0.05);
+    let double_distance = sketch.emd_distance(&shifted(100.0)).unwrap();
+    assert!((double_distance - 2.0 * distance).abs() <= distance * 0.05);
+    assert_eq!(distance, shifted(50.0).emd_distance(&sketch).unwrap());
+
+    assert!(sketch
+        .emd_distance(&DDSketch::unbounded_dense(0.001).unwrap())
+        .is_err());
+    assert!(sketch
+        .emd_distance(&DDSketch::unbounded_dense(0.01).unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_sketch_reserve_value_range() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    sketch.reserve_value_range(-10.0, 1000.0).unwrap();
+    assert!(sketch.is_empty());
+    assert_eq!(None, sketch.get_min());
+
+    let positive_span = (sketch.index_mapping.index(1000.0)
+        - sketch.index_mapping.index(sketch.min_indexed_value)
+        + 1) as usize;
+    let negative_span = (sketch.index_mapping.index(10.0)
+        - sketch.index_mapping.index(sketch.min_indexed_value)
+        + 1) as usize;
+    let positive_capacity = sketch.positive_value_store.capacity_bins();
+    let negative_capacity = sketch.negative_value_store.capacity_bins();
+    assert!(positive_capacity >= positive_span);
+    assert!(negative_capacity >= negative_span);
+
+    let mut expected = DDSketch::unbounded_dense(0.01).unwrap();
+    for value in [1000.0, 1e-300, -10.0, -1e-300, 3.5, 0.0, -2.0, 999.0] {
+        sketch.accept(value);
+        expected.accept(value);
+    }
+    assert_eq!(
+        positive_capacity,
+        sketch.positive_value_store.capacity_bins()
+    );
+    assert_eq!(
+        negative_capacity,
+        sketch.negative_value_store.capacity_bins()
+    );
+    assert!(expected.approx_eq(&sketch, 0.0));
+    assert_eq!(expected.get_min(), sketch.get_min());
+    assert_eq!(expected.get_max(), sketch.get_max());
+
+    // Collapsing stores keep their bins when reserving beyond their maximum number of bins.
+    let mut collapsing = DDSketch::collapsing_lowest_dense(0.01, 100).unwrap();
+    collapsing.accept(5.0);
+    collapsing.reserve_value_range(1.0, 1e9).unwrap();
+    assert_eq!(collapsing.get_count(), 1.0);
+    assert_eq!(
+        collapsing.index_mapping.index(5.0),
+        collapsing.positive_value_store.get_min_index()
+    );
+    assert!(collapsing.positive_value_store.capacity_bins() <= 100);
+
+    assert!(sketch.reserve_value_range(1.0, -1.0).is_err());
+    assert!(sketch.reserve_value_range(f64::NAN, 1.0).is_err());
+    assert!(sketch.reserve_value_range(0.0, f64::INFINITY).is_err());
+}
+
+#[test]
+fn test_sketch_time_windowed() {
+    let close =
+        |value: Option<f64>, expected: f64| (value.unwrap() - expected).abs() <= expected * 0.011;
+    let mut windowed = TimeWindowedSketch::new(0.01, 1000, 3).unwrap();
+    assert_eq!(None, windowed.query_last(3, 0.5));
+
+    for i in 0..100 {
+        windowed.accept_at(1000.0, i * 10);
+        windowed.accept_at(1.0, 1000 + i * 10);
+    }
+    assert!(close(windowed.query_last(1, 0.5), 1.0));
+    assert!(close(windowed.query_last(3, 1.0), 1000.0));
+
+    // The first window expires once a value lands three windows later.
+    windowed.accept_at(10.0, 3500);
+    assert!(close(windowed.query_last(3, 1.0), 10.0));
+    assert!(close(windowed.query_last(10, 0.0), 1.0));
+    assert!(close(windowed.query_last(1, 0.5), 10.0));
+
+    // Values older than the ring are ignored, while late ones within it are recorded.
+    windowed.accept_at(1e6, 500);
+    windowed.accept_at(100.0, 2500);
+    assert!(close(windowed.query_last(3, 1.0), 100.0));
+
+    // A gap larger than the ring expires every window.
+    windowed.accept_at(5.0, 100_000);
+    assert!(close(windowed.query_last(3, 0.0), 5.0));
+    assert!(close(windowed.query_last(3, 1.0), 5.0));
+
+    assert!(TimeWindowedSketch::new(0.01, 0, 3).is_err());
+    assert!(TimeWindowedSketch::new(0.01, 1000, 0).is_err());
+}
+
+#[test]
+fn test_sketch_get_sum_kahan() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    assert_eq!(None, sketch.get_sum_kahan());
+    sketch.accept(1e17);
+    sketch.accept(-1e17);
+    for i in 0..20000 {
+        sketch.accept(1.0 + (i % 1000) as f64 * 1e3f64.powi(i % 3));
+    }
+
+    // The exact sum of the terms, with the partials of Shewchuk's algorithm.
+    let mut partials: Vec<f64> = Vec::new();
+    let terms = sketch
+        .negative_value_store
+        .get_ascending_iter()
+        .map(|bin| -sketch.index_mapping.value(bin.index) * bin.count)
+        .chain(
+            sketch
+                .positive_value_store
+                .get_ascending_iter()
+                .map(|bin| sketch.index_mapping.value(bin.index) * bin.count),
+        );
+    for mut x in terms {
+        let mut i = 0;
+        for j in 0..partials.len() {
+            let mut y = partials[j];
+            if x.abs() < y.abs() {
+                std::mem::swap(&mut x, &mut y);
+            }
+            let high = x + y;
+            let low = y - (high - x);
+            if low != 0.0 {
+                partials[i] = low;
+                i += 1;
+            }
+            x = high;
+        }
+        partials.truncate(i);
+        partials.push(x);
+    }
+    let reference: f64 = partials.iter().sum();
+
+    let kahan = sketch.get_sum_kahan().unwrap();
+    let naive = sketch.get_sum().unwrap();
+    assert!((kahan - reference).abs() < (naive - reference).abs());
+    assert!((kahan - reference).abs() <= reference * 1e-12);
+}
+
+#[test]
+fn test_sketch_encode_to_shared_output() {
+    let mut sketches = Vec::new();
+    for n in [100, 7] {
+        let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+        for i in 0..n {
+            sketch.accept(i as f64 * 1.5 - 20.0);
+        }
+        sketches.push(sketch);
+    }
+
+    let mut output = Output::with_capacity(16);
+    assert!(output.is_empty());
+    let mut ends = Vec::new();
+    for sketch in &sketches {
+        sketch.encode_to(&mut output).unwrap();
+        ends.push(output.len());
+    }
+    let bytes = output.trim();
+    assert_eq!(sketches[0].encode().unwrap(), bytes[..ends[0]]);
+
+    let mut stream = Vec::new();
+    let mut start = 0;
+    for end in ends {
+        let mut prefix = Output::with_capacity(9);
+        sketches_rust::serde::encode_var_u64(&mut prefix, (end - start) as u64).unwrap();
+        stream.extend(prefix.trim());
+        stream.extend_from_slice(&bytes[start..end]);
+        start = end;
+    }
+    let decoded: Vec<DDSketch> = DDSketch::decode_stream(Cursor::new(&stream))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(2, decoded.len());
+    for (sketch, decoded) in sketches.iter().zip(decoded.iter()) {
+        assert!(sketch == decoded);
+    }
+}
+
+#[test]
+fn test_sketch_region_of() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    let max = sketch.max_indexed_value;
+    assert_eq!(Region::Positive, sketch.region_of(1.0));
+    assert_eq!(Region::Positive, sketch.region_of(max));
+    assert_eq!(Region::Negative, sketch.region_of(-1.0));
+    assert_eq!(Region::Zero, sketch.region_of(0.0));
+    assert_eq!(Region::Zero, sketch.region_of(-0.0));
+    assert_eq!(
+        Region::Zero,
+        sketch.region_of(sketch.min_indexed_value / 2.0)
+    );
+    assert_eq!(Region::OutOfRange, sketch.region_of(max * 2.0));
+    assert_eq!(Region::OutOfRange, sketch.region_of(f64::NEG_INFINITY));
+
+    sketch.set_accept_bounds(-10.0, 10.0).unwrap();
+    assert_eq!(Region::OutOfRange, sketch.region_of(11.0));
+    assert_eq!(Region::Negative, sketch.region_of(-10.0));
+
+    for value in [1.0, -1.0, 0.0, 11.0, max * 2.0] {
+        let before = (
+            sketch.positive_value_store.get_total_count(),
+            sketch.negative_value_store.get_total_count(),
+            sketch.zero_count,
+        );
+        sketch.accept(value);
+        let after = (
+            sketch.positive_value_store.get_total_count(),
+            sketch.negative_value_store.get_total_count(),
+            sketch.zero_count,
+        );
+        let expected = match sketch.region_of(value) {
+            Region::Positive => (before.0 + 1.0, before.1, before.2),
+            Region::Negative => (before.0, before.1 + 1.0, before.2),
+            Region::Zero => (before.0, before.1, before.2 + 1.0),
+            Region::OutOfRange => before,
+        };
+        assert_eq!(expected, after);
+    }
+}